@@ -0,0 +1,22 @@
+-- Accounts created before passwords were hashed keep their rows, but their plaintext
+-- passwords shouldn't be kept around. `!` never verifies, so the owners have to reset it.
+UPDATE users SET password = '!' WHERE password NOT LIKE '$argon2%';
+
+ALTER TABLE users ADD PRIMARY KEY (id);
+
+-- Emails are stored lowercased and trimmed, as `normalize_email` does
+UPDATE users SET email = lower(trim(email)) WHERE email <> lower(trim(email));
+
+-- Only one account keeps an email used more than once, the oldest one that can still
+-- log in. The others keep their rows under an address that can't receive mail,
+-- e.g. `a@b.com.<id>.invalid`.
+UPDATE users SET email = users.email || '.' || users.id || '.invalid'
+FROM (
+  SELECT id, row_number() OVER (
+    PARTITION BY email ORDER BY password LIKE '$argon2%' DESC, created_at, id
+  ) AS n
+  FROM users
+) duplicates
+WHERE duplicates.id = users.id AND duplicates.n > 1;
+
+ALTER TABLE users ADD CONSTRAINT users_email_key UNIQUE (email);
@@ -0,0 +1,23 @@
+CREATE TYPE user_role AS ENUM ('admin', 'editor', 'viewer');
+
+ALTER TABLE users ADD COLUMN role user_role NOT NULL DEFAULT 'viewer';
+
+-- Accounts predating roles, the oldest one that can log in becomes admin so someone can
+-- manage the rest. Without one there is no admin, and the next account to register
+-- becomes it, see `handlers::auth::register`.
+UPDATE users SET role = 'admin'
+WHERE id = (
+  SELECT id FROM users WHERE password LIKE '$argon2%' ORDER BY created_at LIMIT 1
+);
+
+-- Overrides the defaults of a role on one table, admins always have every permission
+CREATE TABLE IF NOT EXISTS table_permissions (
+  role user_role not null,
+  table_name text not null,
+  can_read boolean not null default false,
+  can_insert boolean not null default false,
+  can_update boolean not null default false,
+  can_delete boolean not null default false,
+  can_alter boolean not null default false,
+  primary key (role, table_name)
+);
@@ -0,0 +1,14 @@
+-- Limits the rows a role can see or change in a table, admins are exempt.
+-- `filter` is a row filter, where the string "$current_user" stands for the caller's id.
+CREATE TABLE IF NOT EXISTS row_policies (
+  id uuid not null default gen_random_uuid() primary key,
+  created_at timestamp with time zone not null default now(),
+  table_name text not null,
+  role user_role not null,
+  filter jsonb not null,
+  for_read boolean not null default true,
+  for_update boolean not null default true,
+  for_delete boolean not null default true
+);
+
+CREATE INDEX IF NOT EXISTS row_policies_table_name_role_idx ON row_policies (table_name, role);
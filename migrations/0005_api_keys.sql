@@ -0,0 +1,19 @@
+-- Keys act as the user that created them, narrowed down by their scopes.
+-- Only a SHA-256 hash of the key is stored, the key itself is shown once.
+CREATE TABLE IF NOT EXISTS api_keys (
+  id uuid not null default gen_random_uuid() primary key,
+  created_at timestamp with time zone not null default now(),
+  user_id uuid not null references users (id) on delete cascade,
+  name text not null,
+  -- Start of the key, to tell keys apart without revealing them
+  prefix text not null,
+  key_hash text not null unique,
+  read_only boolean not null default true,
+  -- Every table the user can access when NULL
+  tables text[],
+  expires_at timestamp with time zone,
+  last_used_at timestamp with time zone,
+  revoked_at timestamp with time zone
+);
+
+CREATE INDEX IF NOT EXISTS api_keys_user_id_idx ON api_keys (user_id);
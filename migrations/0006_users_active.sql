@@ -0,0 +1,2 @@
+-- Deactivated users can't log in or use their API keys
+ALTER TABLE users ADD COLUMN IF NOT EXISTS is_active boolean NOT NULL DEFAULT true;
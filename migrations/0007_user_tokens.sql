@@ -0,0 +1,14 @@
+ALTER TABLE users ADD COLUMN IF NOT EXISTS email_verified_at timestamp with time zone;
+
+-- Single use tokens sent by email, only a SHA-256 hash is stored
+CREATE TABLE IF NOT EXISTS user_tokens (
+  id uuid not null default gen_random_uuid() primary key,
+  created_at timestamp with time zone not null default now(),
+  user_id uuid not null references users (id) on delete cascade,
+  purpose text not null check (purpose in ('verify_email', 'reset_password')),
+  token_hash text not null unique,
+  expires_at timestamp with time zone not null,
+  used_at timestamp with time zone
+);
+
+CREATE INDEX IF NOT EXISTS user_tokens_user_id_idx ON user_tokens (user_id);
@@ -0,0 +1,2 @@
+-- Tokens issued before the password last changed are rejected, NULL if it never did
+ALTER TABLE users ADD COLUMN IF NOT EXISTS password_changed_at timestamp with time zone;
@@ -0,0 +1,32 @@
+use super::secret;
+
+// Tells API keys apart from JWTs in the `Authorization` header
+pub const KEY_PREFIX: &str = "cms_";
+
+// Shown in listings to identify a key, e.g. `cms_Ab12Cd34`
+const VISIBLE_LEN: usize = KEY_PREFIX.len() + 8;
+
+// What a request made with an API key is limited to, on top of the owner's role
+#[derive(Debug, Clone)]
+pub struct ApiKeyScope {
+    pub read_only: bool,
+    // Every table when None
+    pub tables: Option<Vec<String>>,
+}
+
+impl ApiKeyScope {
+    pub fn can_access(&self, table: &str) -> bool {
+        self.tables
+            .as_ref()
+            .is_none_or(|tables| tables.iter().any(|name| name == table))
+    }
+}
+
+// A new random key, its visible prefix and the hash to store
+pub fn generate() -> (String, String, String) {
+    let key = format!("{}{}", KEY_PREFIX, secret::generate());
+    let prefix = key[..VISIBLE_LEN].to_string();
+    let hash = secret::hash(&key);
+
+    (key, prefix, hash)
+}
@@ -0,0 +1,251 @@
+use std::sync::Arc;
+
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts},
+    http::{header, request::Parts, StatusCode},
+};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{error::AppError, utils::ident::Ident};
+
+use self::{
+    api_key::{ApiKeyScope, KEY_PREFIX},
+    permission::{Permission, Role, TablePermissions, RESERVED_TABLES},
+    token::Keys,
+};
+
+pub mod api_key;
+pub mod one_time;
+pub mod password;
+pub mod permission;
+pub mod policy;
+pub mod secret;
+pub mod token;
+
+// The user a request was made by, taken from the `Authorization: Bearer <token>` header.
+// The token is either a JWT from `/auth/login` or an API key.
+// Rejects the request with 401 when the token is missing or invalid.
+#[derive(Debug, Clone)]
+pub struct AuthUser {
+    pub id: Uuid,
+    // Looked up on every request so role changes apply immediately
+    pub role: Role,
+    // Set when authenticated with an API key
+    pub api_key: Option<ApiKeyScope>,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for AuthUser
+where
+    S: Send + Sync,
+    Arc<Keys>: FromRef<S>,
+    PgPool: FromRef<S>,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        // Already verified by the middleware guarding the route
+        if let Some(user) = parts.extensions.get::<AuthUser>() {
+            return Ok(user.clone());
+        }
+
+        let token = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| AppError::new(StatusCode::UNAUTHORIZED, "Missing bearer token."))?;
+
+        let token = token.trim();
+        let pool = PgPool::from_ref(state);
+
+        let user = match token.starts_with(KEY_PREFIX) {
+            true => Self::from_api_key(&pool, token).await?,
+            false => {
+                let claims = Arc::<Keys>::from_ref(state).verify(token)?;
+
+                // `iat` is in whole seconds, so the change time is truncated to match
+                // and a token from logging in right after the change stays valid
+                let (role, is_current): (Role, bool) = sqlx::query_as(
+                    r#"
+                    SELECT
+                        role,
+                        password_changed_at IS NULL
+                            OR extract(epoch FROM date_trunc('second', password_changed_at)) <= $2
+                    FROM users
+                    WHERE id = $1 AND is_active
+                    "#,
+                )
+                .bind(claims.sub)
+                .bind(claims.iat)
+                .fetch_optional(&pool)
+                .await?
+                .ok_or_else(|| {
+                    AppError::new(
+                        StatusCode::UNAUTHORIZED,
+                        "User no longer exists or is deactivated.",
+                    )
+                })?;
+
+                if !is_current {
+                    return Err(AppError::new(
+                        StatusCode::UNAUTHORIZED,
+                        "The password changed since this token was issued, log in again.",
+                    ));
+                }
+
+                AuthUser {
+                    id: claims.sub,
+                    role,
+                    api_key: None,
+                }
+            }
+        };
+
+        parts.extensions.insert(user.clone());
+
+        Ok(user)
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct ApiKeyRow {
+    user_id: Uuid,
+    role: Role,
+    read_only: bool,
+    tables: Option<Vec<String>>,
+}
+
+impl AuthUser {
+    // Also marks the key as used
+    async fn from_api_key(pool: &PgPool, key: &str) -> Result<Self, AppError> {
+        let row = sqlx::query_as::<_, ApiKeyRow>(
+            r#"
+            UPDATE api_keys AS k SET last_used_at = now()
+            FROM users AS u
+            WHERE
+                k.key_hash = $1
+                AND k.revoked_at IS NULL
+                AND (k.expires_at IS NULL OR k.expires_at > now())
+                AND u.id = k.user_id
+                AND u.is_active
+            RETURNING k.user_id, u.role, k.read_only, k.tables
+            "#,
+        )
+        .bind(secret::hash(key))
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| {
+            AppError::new(
+                StatusCode::UNAUTHORIZED,
+                "Invalid, expired or revoked API key.",
+            )
+        })?;
+
+        Ok(AuthUser {
+            id: row.user_id,
+            role: row.role,
+            api_key: Some(ApiKeyScope {
+                read_only: row.read_only,
+                tables: row.tables,
+            }),
+        })
+    }
+
+    // For endpoints that manage accounts and credentials
+    pub fn require_session(&self) -> Result<(), AppError> {
+        if self.api_key.is_some() {
+            return Err(AppError::new(
+                StatusCode::FORBIDDEN,
+                "API keys can't be used here, log in instead.",
+            ));
+        }
+
+        Ok(())
+    }
+
+    // For resources belonging to one user
+    pub fn require_self_or_admin(&self, user_id: Uuid) -> Result<(), AppError> {
+        if self.id == user_id {
+            return self.require_session();
+        }
+
+        self.require_admin()
+    }
+
+    pub fn require_admin(&self) -> Result<(), AppError> {
+        self.require_session()?;
+
+        if self.role != Role::Admin {
+            return Err(AppError::new(
+                StatusCode::FORBIDDEN,
+                "Only admins can do this.",
+            ));
+        }
+
+        Ok(())
+    }
+
+    // Fails with 403 unless the user's role has `permission` on `table`
+    pub async fn authorize(
+        &self,
+        pool: &PgPool,
+        table: &Ident,
+        permission: Permission,
+    ) -> Result<(), AppError> {
+        if RESERVED_TABLES.contains(&table.as_str()) {
+            return Err(AppError::new(
+                StatusCode::FORBIDDEN,
+                format!("Table `{}` is reserved.", table.as_str()),
+            ));
+        }
+
+        if let Some(ref scope) = self.api_key {
+            if scope.read_only && permission != Permission::Read {
+                return Err(AppError::new(
+                    StatusCode::FORBIDDEN,
+                    "This API key is read-only.",
+                ));
+            }
+
+            if !scope.can_access(table.as_str()) {
+                return Err(AppError::new(
+                    StatusCode::FORBIDDEN,
+                    format!("This API key can't access table `{}`.", table.as_str()),
+                ));
+            }
+        }
+
+        if self.role == Role::Admin {
+            return Ok(());
+        }
+
+        let permissions = sqlx::query_as::<_, TablePermissions>(
+            r#"
+            SELECT can_read, can_insert, can_update, can_delete, can_alter
+            FROM table_permissions
+            WHERE role = $1 AND table_name = $2
+            "#,
+        )
+        .bind(self.role)
+        .bind(table.as_str())
+        .fetch_optional(pool)
+        .await?
+        .unwrap_or_else(|| TablePermissions::default_for(self.role));
+
+        if !permissions.allows(permission) {
+            return Err(AppError::new(
+                StatusCode::FORBIDDEN,
+                format!(
+                    "Missing `{}` permission on table `{}`.",
+                    permission.as_str(),
+                    table.as_str()
+                ),
+            ));
+        }
+
+        Ok(())
+    }
+}
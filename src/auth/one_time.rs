@@ -0,0 +1,91 @@
+use axum::http::StatusCode;
+use chrono::{Duration, Utc};
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+use crate::error::AppError;
+
+use super::secret;
+
+// What a token sent by email can be used for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Purpose {
+    VerifyEmail,
+    ResetPassword,
+}
+
+impl Purpose {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Purpose::VerifyEmail => "verify_email",
+            Purpose::ResetPassword => "reset_password",
+        }
+    }
+
+    fn ttl(&self) -> Duration {
+        match self {
+            Purpose::VerifyEmail => Duration::hours(24),
+            Purpose::ResetPassword => Duration::hours(1),
+        }
+    }
+}
+
+// Returns a new token, tokens issued earlier for the same purpose stop working
+pub async fn issue(pool: &PgPool, user_id: Uuid, purpose: Purpose) -> Result<String, AppError> {
+    let token = secret::generate();
+
+    let mut txn = pool.begin().await?;
+
+    sqlx::query(
+        r#"
+        UPDATE user_tokens SET used_at = now()
+        WHERE user_id = $1 AND purpose = $2 AND used_at IS NULL
+        "#,
+    )
+    .bind(user_id)
+    .bind(purpose.as_str())
+    .execute(&mut *txn)
+    .await?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO user_tokens (user_id, purpose, token_hash, expires_at)
+        VALUES ($1, $2, $3, $4)
+        "#,
+    )
+    .bind(user_id)
+    .bind(purpose.as_str())
+    .bind(secret::hash(&token))
+    .bind(Utc::now() + purpose.ttl())
+    .execute(&mut *txn)
+    .await?;
+
+    txn.commit().await?;
+
+    Ok(token)
+}
+
+// Marks the token as used and returns the user it was issued to
+pub async fn consume(
+    conn: &mut PgConnection,
+    token: &str,
+    purpose: Purpose,
+) -> Result<Uuid, AppError> {
+    sqlx::query_scalar(
+        r#"
+        UPDATE user_tokens SET used_at = now()
+        WHERE token_hash = $1 AND purpose = $2 AND used_at IS NULL AND expires_at > now()
+        RETURNING user_id
+        "#,
+    )
+    .bind(secret::hash(token.trim()))
+    .bind(purpose.as_str())
+    .fetch_optional(conn)
+    .await?
+    .ok_or_else(|| {
+        AppError::new(
+            StatusCode::BAD_REQUEST,
+            "Invalid, expired or already used token.",
+        )
+    })
+}
@@ -0,0 +1,65 @@
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+
+use std::sync::OnceLock;
+
+use crate::error::AppError;
+
+// Hashing is deliberately slow, so it runs off the async runtime
+
+// Returns the hash in PHC string format, which includes the salt and parameters
+pub async fn hash(password: String) -> Result<String, AppError> {
+    let hash = tokio::task::spawn_blocking(move || {
+        let salt = SaltString::generate(&mut OsRng);
+
+        Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+    })
+    .await
+    .map_err(anyhow::Error::from)?
+    .map_err(|err| anyhow::anyhow!("Failed to hash password: {}", err))?;
+
+    Ok(hash)
+}
+
+// Verified against when there is no usable hash, e.g. for an unknown email, so the
+// response takes as long as for a wrong password
+fn dummy_hash() -> &'static str {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+
+    DUMMY_HASH.get_or_init(|| {
+        let salt = SaltString::generate(&mut OsRng);
+
+        Argon2::default()
+            .hash_password(b"", &salt)
+            .expect("hashing with the default parameters succeeds")
+            .to_string()
+    })
+}
+
+// False for a wrong password or a hash that isn't in PHC format, such as the empty
+// hash used for an account that doesn't exist
+pub async fn verify(password: String, hash: String) -> Result<bool, AppError> {
+    let is_valid = tokio::task::spawn_blocking(move || {
+        let (hash, is_usable) = match PasswordHash::new(&hash) {
+            Ok(hash) => (hash, true),
+            Err(_) => (
+                PasswordHash::new(dummy_hash()).expect("valid PHC string"),
+                false,
+            ),
+        };
+
+        let is_valid = Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok();
+
+        is_valid && is_usable
+    })
+    .await
+    .map_err(anyhow::Error::from)?;
+
+    Ok(is_valid)
+}
@@ -0,0 +1,97 @@
+use serde::{Deserialize, Serialize};
+use sqlx::prelude::FromRow;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "user_role", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Admin,
+    Editor,
+    Viewer,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    Read,
+    Insert,
+    Update,
+    Delete,
+    // Create, alter or drop the table
+    Alter,
+}
+
+// Tables backing the CMS itself, never reachable through `/tables` and `/rows`
+pub const RESERVED_TABLES: &[&str] = &[
+    "_sqlx_migrations",
+    "users",
+    "table_permissions",
+    "row_policies",
+    "api_keys",
+    "user_tokens",
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct TablePermissions {
+    #[serde(default)]
+    pub can_read: bool,
+    #[serde(default)]
+    pub can_insert: bool,
+    #[serde(default)]
+    pub can_update: bool,
+    #[serde(default)]
+    pub can_delete: bool,
+    #[serde(default)]
+    pub can_alter: bool,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Admin => "admin",
+            Role::Editor => "editor",
+            Role::Viewer => "viewer",
+        }
+    }
+}
+
+impl Permission {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Permission::Read => "read",
+            Permission::Insert => "insert",
+            Permission::Update => "update",
+            Permission::Delete => "delete",
+            Permission::Alter => "alter",
+        }
+    }
+}
+
+impl TablePermissions {
+    // Used for tables without an entry in `table_permissions`.
+    // Editors manage content but not the schema, viewers can only read.
+    pub fn default_for(role: Role) -> Self {
+        let (content, alter) = match role {
+            Role::Admin => (true, true),
+            Role::Editor => (true, false),
+            Role::Viewer => (false, false),
+        };
+
+        Self {
+            can_read: true,
+            can_insert: content,
+            can_update: content,
+            can_delete: content,
+            can_alter: alter,
+        }
+    }
+
+    pub fn allows(&self, permission: Permission) -> bool {
+        match permission {
+            Permission::Read => self.can_read,
+            Permission::Insert => self.can_insert,
+            Permission::Update => self.can_update,
+            Permission::Delete => self.can_delete,
+            Permission::Alter => self.can_alter,
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::{prelude::FromRow, types::Json, PgPool};
+use uuid::Uuid;
+
+use crate::{error::AppError, handlers::filter::Filter, utils::ident::Ident};
+
+use super::{
+    permission::{Permission, Role},
+    AuthUser,
+};
+
+// Replaced with the id of the user making the request
+pub const CURRENT_USER: &str = "$current_user";
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct RowPolicy {
+    pub id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub table_name: String,
+    pub role: Role,
+    pub filter: Json<Value>,
+    pub for_read: bool,
+    pub for_update: bool,
+    pub for_delete: bool,
+}
+
+impl AuthUser {
+    // The rows of `table` this user may read, update or delete, as a filter.
+    // A row passes when it matches any of the policies that apply, None means every row.
+    //
+    // Policies limit which existing rows are seen or changed,
+    // they aren't checked against inserted or updated values.
+    pub async fn row_policy(
+        &self,
+        pool: &PgPool,
+        table: &Ident,
+        permission: Permission,
+    ) -> Result<Option<Filter>, AppError> {
+        if self.role == Role::Admin {
+            return Ok(None);
+        }
+
+        let filters: Vec<Json<Value>> = sqlx::query_scalar(
+            r#"
+            SELECT filter FROM row_policies
+            WHERE table_name = $1 AND role = $2 AND CASE $3
+                WHEN 'read' THEN for_read
+                WHEN 'update' THEN for_update
+                WHEN 'delete' THEN for_delete
+                ELSE false
+            END
+            ORDER BY created_at
+            "#,
+        )
+        .bind(table.as_str())
+        .bind(self.role)
+        .bind(permission.as_str())
+        .fetch_all(pool)
+        .await?;
+
+        if filters.is_empty() {
+            return Ok(None);
+        }
+
+        let filters = filters
+            .into_iter()
+            .map(|Json(filter)| {
+                serde_json::from_value(bind_current_user(filter, &self.id.to_string()))
+            })
+            .collect::<Result<Vec<Filter>, _>>()?;
+
+        Ok(Some(Filter::Or(filters)))
+    }
+}
+
+fn bind_current_user(value: Value, id: &str) -> Value {
+    match value {
+        Value::String(s) if s == CURRENT_USER => Value::String(id.to_string()),
+        Value::Array(items) => Value::Array(
+            items
+                .into_iter()
+                .map(|item| bind_current_user(item, id))
+                .collect(),
+        ),
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .map(|(key, item)| (key, bind_current_user(item, id)))
+                .collect(),
+        ),
+        value => value,
+    }
+}
@@ -0,0 +1,68 @@
+use axum::http::StatusCode;
+use chrono::{Duration, Utc};
+use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::error::AppError;
+
+// Signs and verifies HS256 JWTs
+pub struct Keys {
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+    ttl: Duration,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Claims {
+    // User ID
+    pub sub: Uuid,
+    pub email: String,
+    pub iat: i64,
+    pub exp: i64,
+}
+
+// Response of `POST /auth/login`
+#[derive(Debug, Serialize)]
+pub struct Token {
+    pub access_token: String,
+    pub token_type: &'static str,
+    // Seconds until the token expires
+    pub expires_in: i64,
+}
+
+impl Keys {
+    pub fn new(secret: &[u8], ttl: Duration) -> Self {
+        Self {
+            encoding: EncodingKey::from_secret(secret),
+            decoding: DecodingKey::from_secret(secret),
+            ttl,
+        }
+    }
+
+    pub fn issue(&self, user_id: Uuid, email: &str) -> Result<Token, AppError> {
+        let now = Utc::now();
+        let claims = Claims {
+            sub: user_id,
+            email: email.to_string(),
+            iat: now.timestamp(),
+            exp: (now + self.ttl).timestamp(),
+        };
+
+        let access_token = jsonwebtoken::encode(&Header::default(), &claims, &self.encoding)
+            .map_err(anyhow::Error::from)?;
+
+        Ok(Token {
+            access_token,
+            token_type: "Bearer",
+            expires_in: self.ttl.num_seconds(),
+        })
+    }
+
+    // Checks the signature and expiry
+    pub fn verify(&self, token: &str) -> Result<Claims, AppError> {
+        jsonwebtoken::decode::<Claims>(token, &self.decoding, &Validation::default())
+            .map(|data| data.claims)
+            .map_err(|_| AppError::new(StatusCode::UNAUTHORIZED, "Invalid or expired token."))
+    }
+}
@@ -1,77 +1,494 @@
 use axum::{
+    extract::rejection::{JsonRejection, PathRejection, QueryRejection},
     http::{self, StatusCode},
     response::{IntoResponse, Response},
+    Json,
 };
-use tracing::error;
+use serde::{ser::SerializeStruct, Serialize, Serializer};
+use sqlx::{error::ErrorKind, postgres::PgDatabaseError};
+use tracing::{error, warn};
+
+use crate::request_id;
+
+// Sent to clients as
+// { "error": { "code": "unique_violation", "message": "...", "status": 409,
+//              "field": "email", "constraint": "users_email_key", "request_id": "..." } }
+// Boxed so `Result<_, AppError>` stays small
+#[derive(Debug)]
+pub struct AppError(Box<ErrorData>);
 
 #[derive(Debug)]
-pub struct AppError {
+struct ErrorData {
+    // Safe to show to clients
     message: String,
     code: http::StatusCode,
+    // Stable and machine readable, e.g. `not_found` or `unique_violation`
+    kind: &'static str,
+    // The column or constraint the error is about, when known
+    field: Option<String>,
+    constraint: Option<String>,
+    // Every problem found when validating a request body
+    errors: Vec<FieldError>,
+    // Only logged, may contain database messages and values
+    details: Option<String>,
+}
+
+// { "field": "columns[1].name", "message": "..." }
+#[derive(Debug, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
 }
 
 impl AppError {
     pub fn new(code: StatusCode, message: impl Into<String>) -> Self {
-        Self {
+        Self(Box::new(ErrorData {
             code,
             message: message.into(),
-        }
+            kind: default_kind(code),
+            field: None,
+            constraint: None,
+            errors: Vec::new(),
+            details: None,
+        }))
+    }
+
+    pub fn validation(errors: Vec<FieldError>) -> Self {
+        let details = errors
+            .iter()
+            .map(|error| format!("{}: {}", error.field, error.message))
+            .collect::<Vec<_>>()
+            .join("; ");
+
+        let mut error = Self::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "The request body is invalid.",
+        )
+        .with_kind("validation_failed")
+        .with_details(details);
+
+        error.0.errors = errors;
+        error
+    }
+
+    // For errors whose cause shouldn't reach the client
+    fn internal(details: String) -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error.").with_details(details)
+    }
+
+    fn with_kind(mut self, kind: &'static str) -> Self {
+        self.0.kind = kind;
+        self
+    }
+
+    fn with_details(mut self, details: String) -> Self {
+        self.0.details = Some(details);
+        self
+    }
+}
+
+fn default_kind(code: StatusCode) -> &'static str {
+    match code {
+        StatusCode::BAD_REQUEST => "bad_request",
+        StatusCode::UNAUTHORIZED => "unauthorized",
+        StatusCode::FORBIDDEN => "forbidden",
+        StatusCode::NOT_FOUND => "not_found",
+        StatusCode::CONFLICT => "conflict",
+        StatusCode::PAYLOAD_TOO_LARGE => "payload_too_large",
+        StatusCode::UNSUPPORTED_MEDIA_TYPE => "unsupported_media_type",
+        StatusCode::UNPROCESSABLE_ENTITY => "unprocessable_entity",
+        StatusCode::SERVICE_UNAVAILABLE => "service_unavailable",
+        code if code.is_server_error() => "internal_error",
+        _ => "error",
     }
 }
 
 impl From<serde_json::error::Error> for AppError {
     fn from(error: serde_json::error::Error) -> Self {
-        AppError {
-            code: StatusCode::INTERNAL_SERVER_ERROR,
-            message: format!("Serde JSON Error:\n{}", error),
-        }
+        AppError::internal(format!("Serde JSON Error:\n{}", error))
     }
 }
 
-// TODO: Pattern match for all error types with their respective status codes
+// Status and error code for SQLSTATEs caused by the request rather than the server,
+// see https://www.postgresql.org/docs/current/errcodes-appendix.html
+const SQLSTATES: &[(&str, StatusCode, &str)] = &[
+    // Integrity constraint violations, messages are built from the constraint
+    ("23505", StatusCode::CONFLICT, "unique_violation"),
+    ("23502", StatusCode::BAD_REQUEST, "not_null_violation"),
+    ("23503", StatusCode::BAD_REQUEST, "foreign_key_violation"),
+    ("23514", StatusCode::UNPROCESSABLE_ENTITY, "check_violation"),
+    ("23P01", StatusCode::CONFLICT, "exclusion_violation"),
+    ("23001", StatusCode::CONFLICT, "restrict_violation"),
+    // Data exceptions
+    (
+        "22P02",
+        StatusCode::BAD_REQUEST,
+        "invalid_text_representation",
+    ),
+    (
+        "22001",
+        StatusCode::BAD_REQUEST,
+        "string_data_right_truncation",
+    ),
+    (
+        "22003",
+        StatusCode::BAD_REQUEST,
+        "numeric_value_out_of_range",
+    ),
+    ("22007", StatusCode::BAD_REQUEST, "invalid_datetime_format"),
+    ("22008", StatusCode::BAD_REQUEST, "datetime_field_overflow"),
+    ("22012", StatusCode::BAD_REQUEST, "division_by_zero"),
+    ("22P05", StatusCode::BAD_REQUEST, "untranslatable_character"),
+    // Syntax errors and access rule violations
+    ("42P01", StatusCode::NOT_FOUND, "undefined_table"),
+    ("42703", StatusCode::BAD_REQUEST, "undefined_column"),
+    ("42704", StatusCode::BAD_REQUEST, "undefined_object"),
+    ("42883", StatusCode::BAD_REQUEST, "undefined_function"),
+    ("42601", StatusCode::BAD_REQUEST, "syntax_error"),
+    ("42804", StatusCode::BAD_REQUEST, "datatype_mismatch"),
+    ("42846", StatusCode::BAD_REQUEST, "cannot_coerce"),
+    ("42P07", StatusCode::CONFLICT, "duplicate_table"),
+    ("42701", StatusCode::CONFLICT, "duplicate_column"),
+    ("42710", StatusCode::CONFLICT, "duplicate_object"),
+    ("42P16", StatusCode::BAD_REQUEST, "invalid_table_definition"),
+    (
+        "42611",
+        StatusCode::BAD_REQUEST,
+        "invalid_column_definition",
+    ),
+    ("42501", StatusCode::FORBIDDEN, "insufficient_privilege"),
+    (
+        "2BP01",
+        StatusCode::CONFLICT,
+        "dependent_objects_still_exist",
+    ),
+    // Worth retrying
+    ("40001", StatusCode::CONFLICT, "serialization_failure"),
+    ("40P01", StatusCode::CONFLICT, "deadlock_detected"),
+    ("55P03", StatusCode::CONFLICT, "lock_not_available"),
+    ("57014", StatusCode::SERVICE_UNAVAILABLE, "query_canceled"),
+    (
+        "53300",
+        StatusCode::SERVICE_UNAVAILABLE,
+        "too_many_connections",
+    ),
+    ("57P01", StatusCode::SERVICE_UNAVAILABLE, "admin_shutdown"),
+];
+
+// Other SQLSTATEs in these classes are also the request's fault
+const SQLSTATE_CLASSES: &[(&str, StatusCode, &str)] = &[
+    ("22", StatusCode::BAD_REQUEST, "invalid_value"),
+    ("23", StatusCode::CONFLICT, "integrity_violation"),
+    ("42", StatusCode::BAD_REQUEST, "invalid_statement"),
+];
+
 impl From<sqlx::Error> for AppError {
     fn from(error: sqlx::Error) -> Self {
-        let code: StatusCode;
+        let details = format!("SQLx Error:\n{}", error);
 
-        match error {
-            sqlx::Error::RowNotFound | sqlx::Error::ColumnNotFound(_) => {
-                code = StatusCode::NOT_FOUND
+        let app_error = match error {
+            sqlx::Error::RowNotFound => AppError::new(StatusCode::NOT_FOUND, "Row not found."),
+            sqlx::Error::ColumnNotFound(ref name) => {
+                let mut error = AppError::new(
+                    StatusCode::NOT_FOUND,
+                    format!("Column `{}` not found.", name),
+                );
+
+                error.0.field = Some(name.clone());
+                error
             }
-            sqlx::Error::TypeNotFound { type_name: _ } => code = StatusCode::NOT_FOUND,
-            sqlx::Error::Database(ref db_err) => match db_err.kind() {
-                sqlx::error::ErrorKind::UniqueViolation => code = StatusCode::CONFLICT,
-                sqlx::error::ErrorKind::NotNullViolation
-                | sqlx::error::ErrorKind::ForeignKeyViolation => code = StatusCode::BAD_REQUEST,
-                sqlx::error::ErrorKind::CheckViolation => code = StatusCode::UNPROCESSABLE_ENTITY,
-                _ => code = StatusCode::INTERNAL_SERVER_ERROR,
+            sqlx::Error::TypeNotFound { ref type_name } => AppError::new(
+                StatusCode::NOT_FOUND,
+                format!("Type `{}` not found.", type_name),
+            ),
+            sqlx::Error::Database(ref db_err) => AppError::from_database(db_err.as_ref())
+                .unwrap_or_else(|| AppError::internal(details.clone())),
+            sqlx::Error::ColumnDecode { .. } => AppError::new(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "A value couldn't be decoded.",
+            )
+            .with_kind("decode_error"),
+            sqlx::Error::PoolTimedOut => AppError::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "The database is busy, try again later.",
+            )
+            .with_kind("pool_timeout"),
+            sqlx::Error::PoolClosed | sqlx::Error::Io(_) | sqlx::Error::Tls(_) => AppError::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "The database is unavailable, try again later.",
+            )
+            .with_kind("database_unavailable"),
+            _ => AppError::internal(details.clone()),
+        };
+
+        app_error.with_details(details)
+    }
+}
+
+impl AppError {
+    // None for SQLSTATEs that point at a server fault
+    fn from_database(db_err: &dyn sqlx::error::DatabaseError) -> Option<Self> {
+        let sqlstate = db_err.code()?;
+        let (code, kind) = SQLSTATES
+            .iter()
+            .find(|(state, _, _)| *state == sqlstate)
+            .or_else(|| {
+                SQLSTATE_CLASSES
+                    .iter()
+                    .find(|(class, _, _)| sqlstate.starts_with(class))
+            })
+            .map(|(_, code, kind)| (*code, *kind))?;
+
+        let pg_err = db_err.try_downcast_ref::<PgDatabaseError>();
+        let constraint = db_err.constraint().map(str::to_string);
+        let field = pg_err.and_then(|err| {
+            err.column()
+                .map(str::to_string)
+                .or_else(|| err.detail().and_then(key_columns))
+        });
+        let detail = pg_err.and_then(PgDatabaseError::detail).unwrap_or_default();
+
+        let message = match db_err.kind() {
+            ErrorKind::UniqueViolation => match field {
+                Some(ref field) => format!("A row with this `{}` already exists.", field),
+                None => "A row with the same values already exists.".to_string(),
             },
-            sqlx::Error::ColumnDecode {
-                index: _,
-                source: _,
-            } => code = StatusCode::UNPROCESSABLE_ENTITY,
-            _ => code = StatusCode::INTERNAL_SERVER_ERROR,
-        }
+            ErrorKind::NotNullViolation => match field {
+                Some(ref field) => format!("`{}` can't be null.", field),
+                None => "A required value is missing.".to_string(),
+            },
+            ErrorKind::ForeignKeyViolation => match detail.contains("is still referenced") {
+                true => "The row is still referenced by another row.".to_string(),
+                false => "The referenced row does not exist.".to_string(),
+            },
+            ErrorKind::CheckViolation => match constraint {
+                Some(ref constraint) => format!("Check constraint `{}` failed.", constraint),
+                None => "A check constraint failed.".to_string(),
+            },
+            _ if sqlstate == "23P01" => match constraint {
+                Some(ref constraint) => {
+                    format!("The row conflicts with another row under `{}`.", constraint)
+                }
+                None => "The row conflicts with another row.".to_string(),
+            },
+            // The database message may quote values, e.g.
+            // `invalid input syntax for type integer: "abc"`, so it's only in the details
+            _ if sqlstate.starts_with("22") => invalid_request_message(kind)
+                .unwrap_or("A value is invalid.")
+                .to_string(),
+            _ if sqlstate.starts_with("42") => invalid_request_message(kind)
+                .unwrap_or("The statement is invalid.")
+                .to_string(),
+            _ => match code {
+                StatusCode::SERVICE_UNAVAILABLE => {
+                    "The database is busy, try again later.".to_string()
+                }
+                _ => "The statement conflicts with a concurrent one, try again.".to_string(),
+            },
+        };
 
-        AppError {
-            code,
-            message: format!("SQLx Error:\n{}", error),
+        let mut error = AppError::new(code, message).with_kind(kind);
+
+        error.0.field = field;
+        error.0.constraint = constraint;
+
+        Some(error)
+    }
+}
+
+// Messages for the data exceptions and statement errors in `SQLSTATES`, `field` and
+// `constraint` point at the column or constraint when Postgres reports it
+fn invalid_request_message(kind: &str) -> Option<&'static str> {
+    let message = match kind {
+        "invalid_text_representation" => "A value doesn't have the format of its type.",
+        "string_data_right_truncation" => "A value is too long for its column.",
+        "numeric_value_out_of_range" => "A number is out of range for its type.",
+        "invalid_datetime_format" => "A date or time has an invalid format.",
+        "datetime_field_overflow" => "A date or time is out of range.",
+        "division_by_zero" => "Division by zero.",
+        "untranslatable_character" => "A value contains a character that can't be stored.",
+        "undefined_table" => "The table does not exist.",
+        "undefined_column" => "A column does not exist.",
+        "undefined_object" => "A type or object does not exist.",
+        "undefined_function" => "A function or operator does not exist for these types.",
+        "syntax_error" => "An expression has a syntax error.",
+        "datatype_mismatch" => "A value doesn't match the type of its column.",
+        "cannot_coerce" => "A value can't be converted to the type of its column.",
+        "duplicate_table" => "A table with this name already exists.",
+        "duplicate_column" => "A column with this name already exists.",
+        "duplicate_object" => "An object with this name already exists.",
+        "invalid_table_definition" => "The table definition is invalid.",
+        "invalid_column_definition" => "A column definition is invalid.",
+        "insufficient_privilege" => "The database user isn't allowed to do this.",
+        _ => return None,
+    };
+
+    Some(message)
+}
+
+// Column names from a detail like `Key (email)=(a@b.com) already exists.`,
+// leaving out the values
+fn key_columns(detail: &str) -> Option<String> {
+    let start = detail.find("Key (")? + "Key (".len();
+    let end = start + detail[start..].find(")=(")?;
+
+    Some(detail[start..end].to_string())
+}
+
+// Replaces axum's plain text rejections, pointing at the offending field when serde knows it
+impl From<JsonRejection> for AppError {
+    fn from(rejection: JsonRejection) -> Self {
+        let details = rejection.body_text();
+
+        let app_error = match rejection {
+            JsonRejection::JsonDataError(ref error) => {
+                // axum wraps the serde error in its own error type
+                let source = std::iter::successors(std::error::Error::source(error), |source| {
+                    source.source()
+                })
+                .find_map(|source| {
+                    source.downcast_ref::<serde_path_to_error::Error<serde_json::Error>>()
+                });
+
+                match source {
+                    Some(source) => {
+                        let field = source.path().to_string();
+                        let message = source.inner().to_string();
+
+                        AppError::validation(vec![FieldError { field, message }])
+                    }
+                    None => AppError::new(StatusCode::UNPROCESSABLE_ENTITY, details.clone())
+                        .with_kind("validation_failed"),
+                }
+            }
+            JsonRejection::JsonSyntaxError(ref error) => {
+                let message = std::error::Error::source(error)
+                    .map(|source| format!("Malformed JSON: {}", source))
+                    .unwrap_or_else(|| "Malformed JSON.".to_string());
+
+                AppError::new(StatusCode::BAD_REQUEST, message).with_kind("invalid_json")
+            }
+            JsonRejection::MissingJsonContentType(_) => AppError::new(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "Expected a `Content-Type: application/json` header.",
+            ),
+            rejection => AppError::new(rejection.status(), rejection.body_text()),
+        };
+
+        app_error.with_details(details)
+    }
+}
+
+impl From<QueryRejection> for AppError {
+    fn from(rejection: QueryRejection) -> Self {
+        AppError::new(StatusCode::BAD_REQUEST, rejection.body_text()).with_kind("invalid_query")
+    }
+}
+
+// e.g. `/users/abc` where an id is expected
+impl From<PathRejection> for AppError {
+    fn from(rejection: PathRejection) -> Self {
+        match rejection {
+            PathRejection::FailedToDeserializePathParams(ref error) => {
+                AppError::new(StatusCode::BAD_REQUEST, error.body_text()).with_kind("invalid_path")
+            }
+            rejection => AppError::new(rejection.status(), rejection.body_text()),
         }
     }
 }
 
 impl From<anyhow::Error> for AppError {
     fn from(error: anyhow::Error) -> Self {
-        AppError {
-            code: StatusCode::INTERNAL_SERVER_ERROR,
-            message: format!("Anyhow Error:\n{}", error),
+        AppError::internal(format!("Anyhow Error:\n{}", error))
+    }
+}
+
+// Only what's safe to send, `details` stays out
+impl Serialize for AppError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let error = &self.0;
+        let mut state = serializer.serialize_struct("AppError", 6)?;
+
+        state.serialize_field("code", error.kind)?;
+        state.serialize_field("message", &error.message)?;
+        state.serialize_field("status", &error.code.as_u16())?;
+
+        if let Some(ref field) = error.field {
+            state.serialize_field("field", field)?;
+        }
+
+        if let Some(ref constraint) = error.constraint {
+            state.serialize_field("constraint", constraint)?;
         }
+
+        if !error.errors.is_empty() {
+            state.serialize_field("errors", &error.errors)?;
+        }
+
+        state.end()
     }
 }
 
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    error: ErrorWithRequestId<'a>,
+}
+
+#[derive(Serialize)]
+struct ErrorWithRequestId<'a> {
+    #[serde(flatten)]
+    error: &'a AppError,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+}
+
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
-        error!("{} {:<12} - {}", "ERROR", self.code, self.message);
+        let code = self.0.code;
+        let details = self.0.details.as_deref().unwrap_or(&self.0.message);
+
+        if code.is_server_error() {
+            error!("{} {:<12} - {}", "ERROR", code, details);
+        } else {
+            warn!("{} {:<12} - {}", "ERROR", code, details);
+        }
+
+        let body = ErrorBody {
+            error: ErrorWithRequestId {
+                error: &self,
+                request_id: request_id::current(),
+            },
+        };
+
+        (code, Json(body)).into_response()
+    }
+}
 
-        (self.code, self.message).into_response()
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_columns_leave_out_values() {
+        assert_eq!(
+            key_columns("Key (email)=(a@b.com) already exists.").as_deref(),
+            Some("email")
+        );
+        assert_eq!(
+            key_columns("Key (post_id, locale)=(7, en) already exists.").as_deref(),
+            Some("post_id, locale")
+        );
+        assert_eq!(key_columns("Failing row contains (1, x)."), None);
+    }
+
+    #[test]
+    fn invalid_requests_have_fixed_messages() {
+        let states = SQLSTATES
+            .iter()
+            .filter(|(state, _, _)| state.starts_with("22") || state.starts_with("42"));
+
+        for (state, _, kind) in states {
+            assert!(invalid_request_message(kind).is_some(), "{}", state);
+        }
     }
 }
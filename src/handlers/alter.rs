@@ -0,0 +1,247 @@
+use std::collections::HashSet;
+
+use serde::Serialize;
+use sqlx::PgConnection;
+use tracing::debug;
+
+use crate::{
+    error::AppError,
+    utils::{ident::Ident, schema::ConstraintSchema},
+};
+
+use super::column::column_list;
+
+// One step of a schema change, e.g. `DROP COLUMN "title"` or `DROP TABLE "posts"`
+#[derive(Debug, Clone, Serialize)]
+pub struct AlterStep {
+    pub sql: String,
+    // Loses or rewrites existing data, i.e. dropping a column or changing its type
+    pub destructive: bool,
+}
+
+// Response of a schema change, the statements only ran when `applied` is true
+#[derive(Debug, Serialize)]
+pub struct SchemaChange {
+    pub statements: Vec<String>,
+    pub steps: Vec<AlterStep>,
+    pub destructive: bool,
+    pub applied: bool,
+}
+
+impl SchemaChange {
+    pub fn new(steps: Vec<AlterStep>, applied: bool) -> Self {
+        Self {
+            statements: steps.iter().map(|step| step.sql.clone()).collect(),
+            destructive: steps.iter().any(|step| step.destructive),
+            steps,
+            applied,
+        }
+    }
+}
+
+// Statements that change a table from its current schema to the requested one.
+// Renames can't be combined with other `ALTER TABLE` actions, so they run separately
+// after the actions, which still refer to the old names. Statements other than
+// `ALTER TABLE`, e.g. for a sequence, run right before or after the actions.
+#[derive(Debug)]
+pub struct AlterPlan {
+    table: Ident,
+    // Run before the other actions, which may rely on a constraint being gone,
+    // e.g. dropping NOT NULL from a column that leaves the primary key
+    constraint_drops: Vec<AlterStep>,
+    actions: Vec<AlterStep>,
+    // Whole statements, e.g. `CREATE SEQUENCE ...`
+    before: Vec<AlterStep>,
+    after: Vec<AlterStep>,
+    column_renames: Vec<(Ident, Ident)>,
+    rename_to: Option<Ident>,
+    // Constraints already dropped by an action, e.g. the primary key when moving it
+    dropped: HashSet<String>,
+}
+
+impl AlterPlan {
+    pub fn new(table: Ident) -> Self {
+        Self {
+            table,
+            constraint_drops: Vec::new(),
+            actions: Vec::new(),
+            before: Vec::new(),
+            after: Vec::new(),
+            column_renames: Vec::new(),
+            rename_to: None,
+            dropped: HashSet::new(),
+        }
+    }
+
+    pub fn action(&mut self, sql: String) {
+        self.actions.push(AlterStep {
+            sql,
+            destructive: false,
+        });
+    }
+
+    pub fn destructive_action(&mut self, sql: String) {
+        self.actions.push(AlterStep {
+            sql,
+            destructive: true,
+        });
+    }
+
+    // Drops each constraint once, however many columns ask for it
+    pub fn drop_constraint(&mut self, name: &str) {
+        if self.dropped.insert(name.to_string()) {
+            self.constraint_drops.push(AlterStep {
+                sql: format!("DROP CONSTRAINT {}", Ident::from_catalog(name)),
+                destructive: false,
+            });
+        }
+    }
+
+    pub fn drop_primary_key(&mut self, constraints: &[ConstraintSchema]) {
+        if let Some(primary_key) = constraints
+            .iter()
+            .find(|constraint| constraint.is_primary_key())
+        {
+            self.drop_constraint(&primary_key.name);
+        }
+    }
+
+    // Backs `column` with a new sequence that continues after its largest value, as
+    // declaring it `serial` would
+    pub fn add_sequence(&mut self, column: &Ident, data_type: &str) {
+        let sequence =
+            Ident::from_catalog(&format!("{}_{}_seq", self.table.as_str(), column.as_str()));
+        let regclass = format!("'{}'::regclass", sequence.to_string().replace('\'', "''"));
+
+        self.before.push(AlterStep {
+            sql: format!(
+                "CREATE SEQUENCE {} AS {} OWNED BY {}.{}",
+                sequence, data_type, self.table, column
+            ),
+            destructive: false,
+        });
+        self.action(format!(
+            "ALTER COLUMN {} SET DEFAULT nextval({})",
+            column, regclass
+        ));
+        self.after.push(AlterStep {
+            sql: format!(
+                "SELECT setval({}, COALESCE(max({}), 0) + 1, false) FROM {}",
+                regclass, column, self.table
+            ),
+            destructive: false,
+        });
+    }
+
+    pub fn statement_after(&mut self, sql: String) {
+        self.after.push(AlterStep {
+            sql,
+            destructive: false,
+        });
+    }
+
+    // Replaces the primary key when `requested` has other columns than the current one,
+    // the order doesn't count. An empty key only drops the current one.
+    pub fn set_primary_key(
+        &mut self,
+        constraints: &[ConstraintSchema],
+        current: &[&str],
+        requested: &[&str],
+    ) -> Result<(), AppError> {
+        let mut sorted_current = current.to_vec();
+        let mut sorted_requested = requested.to_vec();
+
+        sorted_current.sort_unstable();
+        sorted_requested.sort_unstable();
+
+        if sorted_current == sorted_requested {
+            return Ok(());
+        }
+
+        self.drop_primary_key(constraints);
+
+        if !requested.is_empty() {
+            self.action(format!("ADD PRIMARY KEY ({})", column_list(requested)?));
+        }
+
+        Ok(())
+    }
+
+    pub fn rename_column(&mut self, from: Ident, to: Ident) {
+        self.column_renames.push((from, to));
+    }
+
+    pub fn rename_to(&mut self, name: Ident) {
+        self.rename_to = Some(name);
+    }
+
+    fn actions(&self) -> impl Iterator<Item = &AlterStep> {
+        self.constraint_drops.iter().chain(self.actions.iter())
+    }
+
+    pub fn steps(&self) -> Vec<AlterStep> {
+        let renames = self
+            .column_renames
+            .iter()
+            .map(|(from, to)| format!("RENAME COLUMN {} TO {}", from, to))
+            .chain(
+                self.rename_to
+                    .iter()
+                    .map(|name| format!("RENAME TO {}", name)),
+            )
+            .map(|sql| AlterStep {
+                sql,
+                destructive: false,
+            });
+
+        self.before
+            .iter()
+            .chain(self.actions())
+            .chain(self.after.iter())
+            .cloned()
+            .chain(renames)
+            .collect()
+    }
+
+    pub fn statements(&self) -> Vec<String> {
+        let mut statements: Vec<String> = self.before.iter().map(|step| step.sql.clone()).collect();
+
+        let actions: Vec<&str> = self.actions().map(|step| step.sql.as_str()).collect();
+
+        if !actions.is_empty() {
+            statements.push(format!("ALTER TABLE {} {}", self.table, actions.join(", ")));
+        }
+
+        statements.extend(self.after.iter().map(|step| step.sql.clone()));
+
+        for (from, to) in self.column_renames.iter() {
+            statements.push(format!(
+                "ALTER TABLE {} RENAME COLUMN {} TO {}",
+                self.table, from, to
+            ));
+        }
+
+        if let Some(ref name) = self.rename_to {
+            statements.push(format!("ALTER TABLE {} RENAME TO {}", self.table, name));
+        }
+
+        statements
+    }
+
+    pub async fn execute(&self, conn: &mut PgConnection) -> Result<(), AppError> {
+        for sql in self.statements() {
+            debug!("{}", sql);
+
+            sqlx::query(&sql).execute(&mut *conn).await?;
+        }
+
+        Ok(())
+    }
+
+    pub fn summary(&self, applied: bool) -> SchemaChange {
+        SchemaChange {
+            statements: self.statements(),
+            ..SchemaChange::new(self.steps(), applied)
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use axum::{extract::State, http::StatusCode, response::Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::FromRow, PgPool};
+use uuid::Uuid;
+
+use crate::{
+    auth::{api_key, permission::Role, AuthUser},
+    error::AppError,
+    utils::ident::Ident,
+};
+
+use super::extract::{Json, Path};
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct ApiKey {
+    id: Uuid,
+    created_at: DateTime<Utc>,
+    user_id: Uuid,
+    name: String,
+    prefix: String,
+    read_only: bool,
+    tables: Option<Vec<String>>,
+    expires_at: Option<DateTime<Utc>>,
+    last_used_at: Option<DateTime<Utc>>,
+    revoked_at: Option<DateTime<Utc>>,
+}
+
+// Response of `POST /api-keys`, the only time the key is sent
+#[derive(Debug, Serialize)]
+pub struct CreatedApiKey {
+    key: String,
+    #[serde(flatten)]
+    api_key: ApiKey,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewApiKey {
+    name: String,
+    #[serde(default = "default_read_only")]
+    read_only: bool,
+    // Limits the key to these tables
+    tables: Option<Vec<String>>,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+fn default_read_only() -> bool {
+    true
+}
+
+const API_KEY_COLUMNS: &str =
+    "id, created_at, user_id, name, prefix, read_only, tables, expires_at, last_used_at, revoked_at";
+
+pub async fn create_api_key(
+    State(pool): State<PgPool>,
+    user: AuthUser,
+    Json(body): Json<NewApiKey>,
+) -> Result<(StatusCode, axum::Json<CreatedApiKey>), AppError> {
+    user.require_session()?;
+
+    if body.name.trim().is_empty() {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            "API key name can't be empty.",
+        ));
+    }
+
+    let tables = match body.tables {
+        Some(ref tables) => Some(
+            tables
+                .iter()
+                .map(|name| Ident::parse(name).map(|ident| ident.as_str().to_string()))
+                .collect::<Result<Vec<String>, AppError>>()?,
+        ),
+        None => None,
+    };
+
+    let (key, prefix, hash) = api_key::generate();
+
+    let api_key = sqlx::query_as::<_, ApiKey>(&format!(
+        r#"
+        INSERT INTO api_keys (user_id, name, prefix, key_hash, read_only, tables, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        RETURNING {}
+        "#,
+        API_KEY_COLUMNS
+    ))
+    .bind(user.id)
+    .bind(body.name.trim())
+    .bind(prefix)
+    .bind(hash)
+    .bind(body.read_only)
+    .bind(tables)
+    .bind(body.expires_at)
+    .fetch_one(&pool)
+    .await?;
+
+    Ok((
+        StatusCode::CREATED,
+        axum::Json(CreatedApiKey { key, api_key }),
+    ))
+}
+
+// The user's own keys, admins see everyone's
+pub async fn get_api_keys(
+    State(pool): State<PgPool>,
+    user: AuthUser,
+) -> Result<axum::Json<Vec<ApiKey>>, AppError> {
+    user.require_session()?;
+
+    let api_keys = sqlx::query_as::<_, ApiKey>(&format!(
+        "SELECT {} FROM api_keys WHERE $1 OR user_id = $2 ORDER BY created_at DESC",
+        API_KEY_COLUMNS
+    ))
+    .bind(user.role == Role::Admin)
+    .bind(user.id)
+    .fetch_all(&pool)
+    .await?;
+
+    Ok(axum::Json(api_keys))
+}
+
+// Revoked keys are kept so their usage can still be looked up
+pub async fn revoke_api_key(
+    State(pool): State<PgPool>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, AppError> {
+    user.require_session()?;
+
+    let result = sqlx::query(
+        r#"
+        UPDATE api_keys SET revoked_at = now()
+        WHERE id = $1 AND revoked_at IS NULL AND ($2 OR user_id = $3)
+        "#,
+    )
+    .bind(id)
+    .bind(user.role == Role::Admin)
+    .bind(user.id)
+    .execute(&pool)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(AppError::new(
+            StatusCode::NOT_FOUND,
+            format!("API key `{}` not found or already revoked.", id),
+        ));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,266 @@
+use std::sync::Arc;
+
+use axum::{extract::State, http::StatusCode};
+use serde::Deserialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    auth::{
+        one_time::{self, Purpose},
+        password,
+        permission::Role,
+        token::{Keys, Token},
+        AuthUser,
+    },
+    error::AppError,
+    mail::Outbox,
+    state::Registration,
+};
+
+use super::{
+    extract::Json,
+    user::{
+        normalize_email, revoke_credentials, send_email_verification, validate_name,
+        validate_password, User, USER_COLUMNS,
+    },
+};
+
+#[derive(Debug, Deserialize)]
+pub struct Register {
+    email: String,
+    password: String,
+    first_name: String,
+    last_name: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Login {
+    email: String,
+    password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VerifyEmail {
+    token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PasswordResetRequest {
+    email: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PasswordReset {
+    token: String,
+    new_password: String,
+}
+
+pub async fn register(
+    State(pool): State<PgPool>,
+    State(outbox): State<Arc<Outbox>>,
+    State(registration): State<Registration>,
+    Json(body): Json<Register>,
+) -> Result<(StatusCode, Json<User>), AppError> {
+    let email = normalize_email(&body.email)?;
+
+    validate_password(&body.password)?;
+    validate_name("first_name", &body.first_name)?;
+    validate_name("last_name", &body.last_name)?;
+
+    let hash = password::hash(body.password).await?;
+
+    let mut txn = pool.begin().await?;
+
+    // Held until commit, so two first registrations can't both become admin
+    sqlx::query("SELECT pg_advisory_xact_lock(hashtext('register'))")
+        .execute(&mut *txn)
+        .await?;
+
+    let has_admin: bool =
+        sqlx::query_scalar("SELECT EXISTS (SELECT FROM users WHERE role = 'admin')")
+            .fetch_one(&mut *txn)
+            .await?;
+
+    // The first user becomes the admin, everyone else starts as a viewer
+    let role = match (has_admin, registration.is_open) {
+        (false, _) => Role::Admin,
+        (true, true) => Role::Viewer,
+        (true, false) => {
+            return Err(AppError::new(
+                StatusCode::FORBIDDEN,
+                "Registration is closed, ask an admin for an account.",
+            ))
+        }
+    };
+
+    let user = sqlx::query_as::<_, User>(&format!(
+        r#"
+        INSERT INTO users (email, password, first_name, last_name, role)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING {}
+        "#,
+        USER_COLUMNS
+    ))
+    .bind(email)
+    .bind(hash)
+    .bind(body.first_name.trim())
+    .bind(body.last_name.trim())
+    .bind(role)
+    .fetch_one(&mut *txn)
+    .await?;
+
+    txn.commit().await?;
+
+    send_email_verification(&pool, &outbox, &user).await?;
+
+    Ok((StatusCode::CREATED, Json(user)))
+}
+
+pub async fn login(
+    State(pool): State<PgPool>,
+    State(keys): State<Arc<Keys>>,
+    Json(body): Json<Login>,
+) -> Result<Json<Token>, AppError> {
+    let invalid = || AppError::new(StatusCode::UNAUTHORIZED, "Invalid email or password.");
+
+    let email = normalize_email(&body.email).map_err(|_| invalid())?;
+
+    let account: Option<(Uuid, String, bool)> =
+        sqlx::query_as("SELECT id, password, is_active FROM users WHERE email = $1")
+            .bind(&email)
+            .fetch_optional(&pool)
+            .await?;
+
+    // An unknown email is still checked against a hash, so it can't be told apart by
+    // how long the response takes
+    let hash = account
+        .as_ref()
+        .map_or_else(String::new, |(_, hash, _)| hash.clone());
+    let is_valid = password::verify(body.password, hash).await?;
+
+    let (id, _, is_active) = account.filter(|_| is_valid).ok_or_else(invalid)?;
+
+    if !is_active {
+        return Err(AppError::new(
+            StatusCode::FORBIDDEN,
+            "This account is deactivated.",
+        ));
+    }
+
+    Ok(Json(keys.issue(id, &email)?))
+}
+
+// The user the token belongs to
+pub async fn me(State(pool): State<PgPool>, user: AuthUser) -> Result<Json<User>, AppError> {
+    let user =
+        sqlx::query_as::<_, User>(&format!("SELECT {} FROM users WHERE id = $1", USER_COLUMNS))
+            .bind(user.id)
+            .fetch_optional(&pool)
+            .await?
+            .ok_or_else(|| AppError::new(StatusCode::UNAUTHORIZED, "User no longer exists."))?;
+
+    Ok(Json(user))
+}
+
+// Sends a new verification email to the current user
+pub async fn request_email_verification(
+    State(pool): State<PgPool>,
+    State(outbox): State<Arc<Outbox>>,
+    user: AuthUser,
+) -> Result<StatusCode, AppError> {
+    user.require_session()?;
+
+    let user =
+        sqlx::query_as::<_, User>(&format!("SELECT {} FROM users WHERE id = $1", USER_COLUMNS))
+            .bind(user.id)
+            .fetch_one(&pool)
+            .await?;
+
+    if user.email_verified_at.is_some() {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            "Email is already verified.",
+        ));
+    }
+
+    send_email_verification(&pool, &outbox, &user).await?;
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+pub async fn verify_email(
+    State(pool): State<PgPool>,
+    Json(body): Json<VerifyEmail>,
+) -> Result<StatusCode, AppError> {
+    let mut txn = pool.begin().await?;
+
+    let user_id = one_time::consume(&mut txn, &body.token, Purpose::VerifyEmail).await?;
+
+    sqlx::query("UPDATE users SET email_verified_at = now() WHERE id = $1")
+        .bind(user_id)
+        .execute(&mut *txn)
+        .await?;
+
+    txn.commit().await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// Always accepted, so it can't be used to find out which emails have an account
+pub async fn request_password_reset(
+    State(pool): State<PgPool>,
+    State(outbox): State<Arc<Outbox>>,
+    Json(body): Json<PasswordResetRequest>,
+) -> Result<StatusCode, AppError> {
+    let Ok(email) = normalize_email(&body.email) else {
+        return Ok(StatusCode::ACCEPTED);
+    };
+
+    let user_id: Option<Uuid> =
+        sqlx::query_scalar("SELECT id FROM users WHERE email = $1 AND is_active")
+            .bind(&email)
+            .fetch_optional(&pool)
+            .await?;
+
+    if let Some(user_id) = user_id {
+        let token = one_time::issue(&pool, user_id, Purpose::ResetPassword).await?;
+
+        outbox.send_password_reset(&email, &token);
+    }
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+// Following the emailed link also proves the user owns the address
+pub async fn reset_password(
+    State(pool): State<PgPool>,
+    Json(body): Json<PasswordReset>,
+) -> Result<StatusCode, AppError> {
+    validate_password(&body.new_password)?;
+
+    let hash = password::hash(body.new_password).await?;
+
+    let mut txn = pool.begin().await?;
+
+    let user_id = one_time::consume(&mut txn, &body.token, Purpose::ResetPassword).await?;
+
+    sqlx::query(
+        r#"
+        UPDATE users SET
+            password = $1,
+            email_verified_at = COALESCE(email_verified_at, now())
+        WHERE id = $2
+        "#,
+    )
+    .bind(hash)
+    .bind(user_id)
+    .execute(&mut *txn)
+    .await?;
+
+    revoke_credentials(&mut txn, user_id).await?;
+
+    txn.commit().await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -2,26 +2,55 @@ use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use sqlx::{prelude::FromRow, Postgres, QueryBuilder};
 
+use crate::{
+    error::AppError,
+    utils::{
+        self,
+        ident::Ident,
+        schema::{ColumnSchema, ConstraintSchema, TableSchema},
+    },
+};
+
+use super::alter::AlterPlan;
+
 #[derive(Debug, Deserialize)]
 pub struct BuildColumn {
     pub name: String,
     pub data_type: String,
     pub default: Option<Value>, // Optional default value
-    pub is_nullable: bool,      // Sets column to NOT NULL if true
+    // Removes the current default when replacing a table, which is kept when `default`
+    // is left out
+    #[serde(default)]
+    pub drop_default: bool,
+    pub is_nullable: bool, // Sets column to NOT NULL if true
     pub is_primary_key: bool,
     pub is_unique: bool,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColumnState {
+    Added,
+    Removed,
+    Modified,
+    Unchanged,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct EditColumn {
+    // The current name, or the name to add
     pub name: String,
+    // Renames a modified column
+    pub new_name: Option<String>,
     pub data_type: String,
-    pub default: Option<Value>, // Optional default value
-    pub is_nullable: bool,      // Sets column to NOT NULL if true
+    // Left as is on a modified column when not given
+    pub default: Option<Value>,
+    #[serde(default)]
+    pub drop_default: bool,
+    pub is_nullable: bool, // Sets column to NOT NULL if false
     pub is_primary_key: bool,
     pub is_unique: bool,
-    // NOTE: Can this be an enum even if data is from JSON?
-    pub state: String, // "added" | "removed" | "modified" | "unchanged"
+    pub state: ColumnState,
 }
 
 #[derive(Debug, Deserialize, Serialize, FromRow)]
@@ -31,54 +60,567 @@ pub struct InsertOnColumn {
     pub is_db_expression: bool,
 }
 
+// Right hand side of `SET {column} = ...` in an update
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Assignment {
+    // { "value": "Hello" }
+    Value(Value),
+    // { "increment": 1 }, use a negative number to decrement
+    Increment(Value),
+    // { "expression": "now()" }, see `utils::push_db_expression`
+    Expression(Value),
+}
+
+// CONSTRAINT {name} CHECK ({expression})
+#[derive(Debug, Deserialize)]
+pub struct Check {
+    pub name: String,
+    // Boolean SQL expression over the table's columns, e.g. `price > 0`
+    pub expression: String,
+}
+
+impl Check {
+    pub fn definition(&self) -> Result<String, AppError> {
+        Ok(format!(
+            "CONSTRAINT {} CHECK ({})",
+            Ident::parse(&self.name)?,
+            self.expression.trim()
+        ))
+    }
+}
+
 impl BuildColumn {
-    pub fn build_columns(q_builder: &mut QueryBuilder<'_, Postgres>, columns: &[BuildColumn]) {
-        q_builder.push(" (");
-
-        for (i, column) in columns.iter().enumerate() {
-            q_builder.push(format_args!(
-                "{} {}",
-                column.name.as_str(),
-                column.data_type.as_str()
-            ));
+    // Column definitions followed by the table constraints, a primary key over more
+    // than one column is declared as a table constraint
+    pub fn build_columns(
+        q_builder: &mut QueryBuilder<'_, Postgres>,
+        columns: &[BuildColumn],
+        unique: &[Vec<String>],
+        checks: &[Check],
+    ) -> Result<(), AppError> {
+        let primary_key: Vec<&str> = columns
+            .iter()
+            .filter(|column| column.is_primary_key)
+            .map(|column| column.name.as_str())
+            .collect();
+
+        let mut definitions: Vec<String> = Vec::new();
+
+        for column in columns.iter() {
+            let mut definition = format!("{} {}", Ident::parse(&column.name)?, column.data_type);
 
             if !column.is_nullable {
-                q_builder.push(" NOT NULL");
+                definition.push_str(" NOT NULL");
             }
 
             if let Some(ref default) = column.default {
-                Self::build_default(q_builder, default, column);
+                definition.push_str(&format!(" DEFAULT {}", default_expression(default)));
             }
 
-            // NOTE: Assume there's one primary key for now
-            if column.is_primary_key {
-                q_builder.push(" PRIMARY KEY");
+            if column.is_unique {
+                definition.push_str(" UNIQUE");
             }
 
-            if i < columns.len() - 1 {
-                q_builder.push(", ");
+            if column.is_primary_key && primary_key.len() == 1 {
+                definition.push_str(" PRIMARY KEY");
             }
+
+            definitions.push(definition);
+        }
+
+        if primary_key.len() > 1 {
+            definitions.push(format!("PRIMARY KEY ({})", column_list(&primary_key)?));
+        }
+
+        for columns in unique.iter() {
+            let columns: Vec<&str> = columns.iter().map(String::as_str).collect();
+
+            definitions.push(format!("UNIQUE ({})", column_list(&columns)?));
+        }
+
+        for check in checks.iter() {
+            definitions.push(check.definition()?);
         }
 
-        q_builder.push(") ");
+        q_builder.push(format_args!(" ({}) ", definitions.join(", ")));
+
+        Ok(())
     }
+}
 
-    // TODO: Handle default values better if the default is a function such as gen_random_uuid()
-    fn build_default(
-        q_builder: &mut QueryBuilder<'_, Postgres>,
-        default: &Value,
-        column: &BuildColumn,
+// `"a", "b"` for `PRIMARY KEY (...)` and `UNIQUE (...)`
+pub fn column_list(names: &[&str]) -> Result<String, AppError> {
+    let idents = names
+        .iter()
+        .map(|name| Ident::parse(name).map(|ident| ident.to_string()))
+        .collect::<Result<Vec<String>, AppError>>()?;
+
+    Ok(idents.join(", "))
+}
+
+// A quoted literal, or a database expression such as `now()` or `gen_random_uuid()`,
+// see `utils::DB_EXPRESSIONS`
+fn default_expression(default: &Value) -> String {
+    let literal = |s: &str| format!("'{}'", s.replace('\'', "''"));
+
+    match default {
+        Value::String(s) => match utils::db_expression(s) {
+            Some(expression) if expression != "DEFAULT" => expression.to_string(),
+            _ => literal(s),
+        },
+        // e.g. for json columns
+        Value::Array(_) | Value::Object(_) => literal(&default.to_string()),
+        _ => default.to_string(),
+    }
+}
+
+// `current` is the default as stored, e.g. `'draft'::text` or `now()`. The stored text
+// is accepted as is too, so a default read from `GET /tables/:name` counts as unchanged,
+// e.g. `nextval('posts_id_seq'::regclass)`.
+fn is_same_default(default: &Value, current: &str) -> bool {
+    let expression = default_expression(default);
+
+    default.as_str() == Some(current)
+        || strip_literal_cast(current) == expression
+        || (!expression.starts_with('\'') && expression.eq_ignore_ascii_case(current))
+}
+
+// Dropping or replacing it leaves the sequence unused, so new rows need explicit ids
+fn is_sequence_default(default: &str) -> bool {
+    default.starts_with("nextval(")
+}
+
+// `posts_id_seq` from `nextval('posts_id_seq'::regclass)`, quoted as needed
+fn sequence_name(default: &str) -> Option<&str> {
+    default
+        .strip_prefix("nextval('")?
+        .strip_suffix("'::regclass)")
+}
+
+impl BuildColumn {
+    // The change from `current` to this column. The primary key is left out since it's
+    // compared for the whole table, see `table::Table::diff`.
+    pub fn as_change(&self, current: Option<&ColumnSchema>) -> EditColumn {
+        EditColumn {
+            name: self.name.clone(),
+            new_name: None,
+            data_type: self.data_type.clone(),
+            default: self.default.clone(),
+            drop_default: self.drop_default,
+            is_nullable: self.is_nullable && !self.is_primary_key,
+            is_primary_key: false,
+            is_unique: self.is_unique,
+            state: match current {
+                Some(_) => ColumnState::Modified,
+                None => ColumnState::Added,
+            },
+        }
+    }
+}
+
+impl EditColumn {
+    // Adds the actions for this column to `plan`, comparing against the current schema.
+    // The primary key may span other columns, see `table::EditTable::primary_key`.
+    pub fn plan(
+        &self,
+        plan: &mut AlterPlan,
+        schema: &TableSchema,
+        constraints: &[ConstraintSchema],
+    ) -> Result<(), AppError> {
+        let name = Ident::parse(&self.name)?;
+
+        match self.state {
+            ColumnState::Added => {
+                let mut definition = format!("ADD COLUMN {} {}", name, self.data_type);
+
+                if !self.is_nullable {
+                    definition.push_str(" NOT NULL");
+                }
+
+                if let Some(ref default) = self.default {
+                    definition.push_str(&format!(" DEFAULT {}", default_expression(default)));
+                }
+
+                if self.is_unique {
+                    definition.push_str(" UNIQUE");
+                }
+
+                plan.action(definition);
+            }
+            ColumnState::Removed => {
+                schema.column(&self.name)?;
+
+                plan.destructive_action(format!("DROP COLUMN {}", name));
+            }
+            ColumnState::Modified => {
+                let current = schema.column(&self.name)?;
+
+                self.plan_changes(plan, current, constraints);
+
+                if let Some(ref new_name) = self.new_name {
+                    if *new_name != self.name {
+                        plan.rename_column(name, Ident::parse(new_name)?);
+                    }
+                }
+            }
+            ColumnState::Unchanged => {}
+        }
+
+        Ok(())
+    }
+
+    // Everything but the primary key, which may span other columns
+    pub fn plan_changes(
+        &self,
+        plan: &mut AlterPlan,
+        current: &ColumnSchema,
+        constraints: &[ConstraintSchema],
     ) {
-        match (default, column.data_type.as_str()) {
-            (Value::String(s), "uuid") => {
-                q_builder.push(format_args!(" DEFAULT {}", s));
+        let name = current.ident();
+
+        // A serial type is an integer type with a sequence as its default
+        let normalized = normalize_type(&self.data_type);
+        let serial_type = normalized.strip_suffix(" serial");
+        let data_type = serial_type.unwrap_or(&self.data_type);
+
+        if !is_same_type(current, &self.data_type) {
+            plan.destructive_action(format!(
+                "ALTER COLUMN {} SET DATA TYPE {} USING {}::{}",
+                name, data_type, name, data_type
+            ));
+        }
+
+        match (
+            serial_type,
+            current.default.as_deref().and_then(sequence_name),
+        ) {
+            (Some(data_type), None) => plan.add_sequence(&name, data_type),
+            (Some(data_type), Some(sequence)) if !is_same_type(current, data_type) => {
+                plan.statement_after(format!("ALTER SEQUENCE {} AS {}", sequence, data_type))
             }
-            (Value::String(s), _) => {
-                q_builder.push(format_args!(" DEFAULT '{}'", s));
+            _ => {}
+        }
+
+        let is_serial = serial_type.is_some();
+
+        let action = match (&self.default, current.default.as_deref()) {
+            (Some(default), Some(current)) if is_same_default(default, current) => None,
+            (Some(default), _) if !is_serial => Some(format!(
+                "ALTER COLUMN {} SET DEFAULT {}",
+                name,
+                default_expression(default)
+            )),
+            (None, Some(_)) if self.drop_default && !is_serial => {
+                Some(format!("ALTER COLUMN {} DROP DEFAULT", name))
             }
-            _ => {
-                q_builder.push(format_args!(" DEFAULT {}", default));
+            _ => None,
+        };
+
+        if let Some(action) = action {
+            match current.default.as_deref().is_some_and(is_sequence_default) {
+                true => plan.destructive_action(action),
+                false => plan.action(action),
             }
         }
+
+        // Primary key columns are always NOT NULL
+        let is_nullable = self.is_nullable && !self.is_primary_key;
+
+        if is_nullable != current.is_nullable {
+            plan.action(format!(
+                "ALTER COLUMN {} {} NOT NULL",
+                name,
+                if is_nullable { "DROP" } else { "SET" }
+            ));
+        }
+
+        let unique: Vec<&ConstraintSchema> = constraints
+            .iter()
+            .filter(|constraint| constraint.is_unique_on(&current.name))
+            .collect();
+
+        match (self.is_unique, unique.is_empty()) {
+            (true, true) => plan.action(format!("ADD UNIQUE ({})", name)),
+            (false, false) => unique
+                .iter()
+                .for_each(|constraint| plan.drop_constraint(&constraint.name)),
+            _ => {}
+        }
+    }
+}
+
+// Type names as `format_type()` spells them
+const TYPE_ALIASES: &[(&str, &str)] = &[
+    ("int", "integer"),
+    ("int4", "integer"),
+    ("serial", "integer"),
+    ("serial4", "integer"),
+    ("int2", "smallint"),
+    ("smallserial", "smallint"),
+    ("serial2", "smallint"),
+    ("int8", "bigint"),
+    ("bigserial", "bigint"),
+    ("serial8", "bigint"),
+    ("bool", "boolean"),
+    ("float4", "real"),
+    ("float8", "double precision"),
+    ("float", "double precision"),
+    ("decimal", "numeric"),
+    ("varchar", "character varying"),
+    ("char", "character"),
+    ("bpchar", "character"),
+    ("timestamp", "timestamp without time zone"),
+    ("timestamptz", "timestamp with time zone"),
+    ("time", "time without time zone"),
+    ("timetz", "time with time zone"),
+    ("varbit", "bit varying"),
+];
+
+// Lower cases a type, expands aliases and drops whitespace in modifiers and array
+// brackets, e.g. `VARCHAR(20)[]` becomes `character varying(20)[]` and
+// `numeric(10, 2)` becomes `numeric(10,2)`. Serial types become `{integer type} serial`.
+fn normalize_type(data_type: &str) -> String {
+    let data_type = data_type.trim().to_lowercase();
+    let (data_type, arrays) = data_type.split_at(data_type.find('[').unwrap_or(data_type.len()));
+    let arrays: String = arrays.split_whitespace().collect();
+
+    // Modifiers may come before the last words, e.g. `timestamp(3) with time zone`
+    let (name, modifiers) = match (data_type.find('('), data_type.find(')')) {
+        (Some(start), Some(end)) if start < end => (
+            format!("{} {}", &data_type[..start], &data_type[end + 1..]),
+            data_type[start..=end].split_whitespace().collect(),
+        ),
+        _ => (data_type.to_string(), String::new()),
+    };
+    let name = name.split_whitespace().collect::<Vec<&str>>().join(" ");
+
+    let name = match TYPE_ALIASES.iter().find(|(alias, _)| *alias == name) {
+        Some((alias, name)) if alias.contains("serial") => return format!("{} serial", name),
+        Some((_, name)) => name.to_string(),
+        None => name,
+    };
+
+    // `format_type()` puts the precision of times right after the first word
+    match name.split_once(' ') {
+        Some((first @ ("timestamp" | "time"), rest)) => {
+            format!("{}{} {}{}", first, modifiers, rest, arrays)
+        }
+        _ => format!("{}{}{}", name, modifiers, arrays),
+    }
+}
+
+// Compares against `format_type()`, so "int4", "integer" and "serial" all match an
+// integer column
+fn is_same_type(current: &ColumnSchema, data_type: &str) -> bool {
+    let data_type = normalize_type(data_type);
+    let data_type = data_type.strip_suffix(" serial").unwrap_or(&data_type);
+
+    data_type == current.data_type || data_type == current.udt_name
+}
+
+// `'draft'::text` as stored by Postgres becomes `'draft'`
+fn strip_literal_cast(expression: &str) -> &str {
+    match expression.rfind("'::") {
+        Some(i) if expression.starts_with('\'') => &expression[..=i],
+        _ => expression,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn build_column(name: &str, data_type: &str, is_primary_key: bool) -> BuildColumn {
+        BuildColumn {
+            name: name.to_string(),
+            data_type: data_type.to_string(),
+            default: None,
+            drop_default: false,
+            is_nullable: !is_primary_key,
+            is_primary_key,
+            is_unique: false,
+        }
+    }
+
+    fn create_table(columns: &[BuildColumn], unique: &[Vec<String>], checks: &[Check]) -> String {
+        let mut q_builder = QueryBuilder::new("CREATE TABLE \"t\"");
+
+        BuildColumn::build_columns(&mut q_builder, columns, unique, checks).unwrap();
+
+        q_builder.sql().to_string()
+    }
+
+    #[test]
+    fn single_column_primary_key() {
+        let mut title = build_column("title", "text", false);
+        title.default = Some(json!("untitled"));
+        title.is_unique = true;
+
+        assert_eq!(
+            create_table(&[build_column("id", "serial", true), title], &[], &[]),
+            "CREATE TABLE \"t\" (\"id\" serial NOT NULL PRIMARY KEY, \
+             \"title\" text DEFAULT 'untitled' UNIQUE) "
+        );
+    }
+
+    #[test]
+    fn table_constraints() {
+        let columns = [
+            build_column("order_id", "integer", true),
+            build_column("line", "integer", true),
+            build_column("sku", "text", false),
+            build_column("price", "numeric", false),
+        ];
+        let unique = [vec!["order_id".to_string(), "sku".to_string()]];
+        let checks = [Check {
+            name: "positive_price".to_string(),
+            expression: " price > 0 ".to_string(),
+        }];
+
+        assert_eq!(
+            create_table(&columns, &unique, &checks),
+            "CREATE TABLE \"t\" (\"order_id\" integer NOT NULL, \"line\" integer NOT NULL, \
+             \"sku\" text, \"price\" numeric, PRIMARY KEY (\"order_id\", \"line\"), \
+             UNIQUE (\"order_id\", \"sku\"), \
+             CONSTRAINT \"positive_price\" CHECK (price > 0)) "
+        );
+    }
+
+    #[test]
+    fn invalid_constraint_names() {
+        let mut q_builder = QueryBuilder::new("CREATE TABLE \"t\"");
+        let checks = [Check {
+            name: "bad\"name".to_string(),
+            expression: "true".to_string(),
+        }];
+
+        assert!(BuildColumn::build_columns(&mut q_builder, &[], &[], &checks).is_err());
+    }
+
+    #[test]
+    fn type_aliases() {
+        assert_eq!(normalize_type("INT"), "integer");
+        assert_eq!(normalize_type("VARCHAR(20)[]"), "character varying(20)[]");
+        assert_eq!(normalize_type("timestamptz"), "timestamp with time zone");
+        assert_eq!(normalize_type("bigserial"), "bigint serial");
+        assert_eq!(normalize_type("user_role"), "user_role");
+    }
+
+    #[test]
+    fn type_whitespace() {
+        assert_eq!(normalize_type("numeric(10, 2)"), "numeric(10,2)");
+        assert_eq!(normalize_type(" numeric ( 10 ,2 ) "), "numeric(10,2)");
+        assert_eq!(normalize_type("double   precision"), "double precision");
+        assert_eq!(normalize_type("int [ ]"), "integer[]");
+    }
+
+    #[test]
+    fn time_precision_follows_the_first_word() {
+        assert_eq!(
+            normalize_type("timestamp(3) with time zone"),
+            "timestamp(3) with time zone"
+        );
+        assert_eq!(normalize_type("TIME(2)"), "time(2) without time zone");
+    }
+
+    #[test]
+    fn literal_casts() {
+        assert_eq!(strip_literal_cast("'draft'::text"), "'draft'");
+        assert_eq!(strip_literal_cast("'a''b'::character varying"), "'a''b'");
+        assert_eq!(strip_literal_cast("now()"), "now()");
+        assert_eq!(strip_literal_cast("0"), "0");
+    }
+
+    #[test]
+    fn default_expressions() {
+        assert_eq!(default_expression(&json!("draft")), "'draft'");
+        assert_eq!(default_expression(&json!("it's")), "'it''s'");
+        assert_eq!(default_expression(&json!("NOW()")), "now()");
+        assert_eq!(default_expression(&json!("DEFAULT")), "'DEFAULT'");
+        assert_eq!(default_expression(&json!(1.5)), "1.5");
+        assert_eq!(default_expression(&json!(["a'"])), "'[\"a''\"]'");
+    }
+
+    #[test]
+    fn same_defaults() {
+        assert!(is_same_default(&json!("draft"), "'draft'::text"));
+        assert!(is_same_default(&json!("now()"), "now()"));
+        assert!(is_same_default(
+            &json!("current_timestamp"),
+            "CURRENT_TIMESTAMP"
+        ));
+        assert!(is_same_default(&json!(0), "0"));
+        assert!(is_same_default(
+            &json!("nextval('posts_id_seq'::regclass)"),
+            "nextval('posts_id_seq'::regclass)"
+        ));
+        assert!(!is_same_default(&json!("now()"), "'now()'::text"));
+        assert!(!is_same_default(&json!("Draft"), "'draft'::text"));
+    }
+
+    fn edit_column(data_type: &str) -> EditColumn {
+        EditColumn {
+            name: "id".to_string(),
+            new_name: None,
+            data_type: data_type.to_string(),
+            default: None,
+            drop_default: false,
+            is_nullable: false,
+            is_primary_key: false,
+            is_unique: false,
+            state: ColumnState::Modified,
+        }
+    }
+
+    fn current_column(default: Option<&str>) -> ColumnSchema {
+        ColumnSchema {
+            name: "id".to_string(),
+            data_type: "integer".to_string(),
+            base_type: "integer".to_string(),
+            udt_name: "int4".to_string(),
+            is_nullable: false,
+            default: default.map(str::to_string),
+            is_primary_key: false,
+            primary_key_position: None,
+        }
+    }
+
+    fn plan_changes(column: &EditColumn, current: &ColumnSchema) -> Vec<String> {
+        let mut plan = AlterPlan::new(Ident::from_catalog("posts"));
+
+        column.plan_changes(&mut plan, current, &[]);
+
+        plan.statements()
+    }
+
+    #[test]
+    fn serial_types_add_a_sequence() {
+        assert_eq!(
+            plan_changes(&edit_column("bigserial"), &current_column(None)),
+            [
+                "CREATE SEQUENCE \"posts_id_seq\" AS bigint OWNED BY \"posts\".\"id\"",
+                "ALTER TABLE \"posts\" ALTER COLUMN \"id\" SET DATA TYPE bigint USING \"id\"::bigint, \
+                 ALTER COLUMN \"id\" SET DEFAULT nextval('\"posts_id_seq\"'::regclass)",
+                "SELECT setval('\"posts_id_seq\"'::regclass, COALESCE(max(\"id\"), 0) + 1, false) \
+                 FROM \"posts\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn serial_types_keep_their_sequence() {
+        let current = current_column(Some("nextval('posts_id_seq'::regclass)"));
+
+        assert!(plan_changes(&edit_column("serial"), &current).is_empty());
+        assert_eq!(
+            plan_changes(&edit_column("bigserial"), &current),
+            [
+                "ALTER TABLE \"posts\" ALTER COLUMN \"id\" SET DATA TYPE bigint USING \"id\"::bigint",
+                "ALTER SEQUENCE posts_id_seq AS bigint",
+            ]
+        );
     }
 }
@@ -0,0 +1,301 @@
+use axum::http::StatusCode;
+use serde::Deserialize;
+use serde_json::Value;
+use sqlx::{Postgres, QueryBuilder};
+
+use crate::{
+    error::AppError,
+    utils::{self, schema::TableSchema},
+};
+
+// Composable WHERE clause, e.g.
+// { "and": [
+//     { "column": "title", "op": "ilike", "value": "%news%" },
+//     { "not": { "column": "deleted_at", "op": "is_null" } }
+// ] }
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Filter {
+    And(Vec<Filter>),
+    Or(Vec<Filter>),
+    Not(Box<Filter>),
+    #[serde(untagged)]
+    Condition(Condition),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Condition {
+    pub column: String,
+    pub op: Operator,
+    // Array for `in` and `not_in`, two element array for `between`
+    // Ignored by `is_null` and `is_not_null`
+    #[serde(default)]
+    pub value: Value,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Operator {
+    Eq,
+    Neq,
+    Lt,
+    Lte,
+    Gt,
+    Gte,
+    In,
+    NotIn,
+    Like,
+    Ilike,
+    IsNull,
+    IsNotNull,
+    Between,
+}
+
+impl Filter {
+    // Parses a filter passed as JSON in the query string
+    pub fn from_query(filter: &str) -> Result<Self, AppError> {
+        serde_json::from_str(filter).map_err(|err| {
+            AppError::new(StatusCode::BAD_REQUEST, format!("Invalid filter: {}", err))
+        })
+    }
+
+    // Rows must match every filter, None when there are none
+    pub fn all_of(filters: impl IntoIterator<Item = Filter>) -> Option<Self> {
+        let mut filters: Vec<Filter> = filters.into_iter().collect();
+
+        match filters.len() {
+            0 => None,
+            1 => filters.pop(),
+            _ => Some(Filter::And(filters)),
+        }
+    }
+
+    // True when no row can fail the filter, e.g. `{ "and": [] }` or `{ "not": { "or": [] } }`,
+    // which is the same as not filtering at all
+    pub fn matches_every_row(&self) -> bool {
+        self.constant() == Some(true)
+    }
+
+    // The value of a filter that doesn't depend on any condition, empty groups are
+    // pushed as TRUE or FALSE
+    fn constant(&self) -> Option<bool> {
+        match self {
+            Filter::And(filters) => {
+                let values: Vec<Option<bool>> = filters.iter().map(Filter::constant).collect();
+
+                if values.contains(&Some(false)) {
+                    Some(false)
+                } else if values.iter().all(|value| *value == Some(true)) {
+                    Some(true)
+                } else {
+                    None
+                }
+            }
+            Filter::Or(filters) => {
+                let values: Vec<Option<bool>> = filters.iter().map(Filter::constant).collect();
+
+                if values.contains(&Some(true)) {
+                    Some(true)
+                } else if values.iter().all(|value| *value == Some(false)) {
+                    Some(false)
+                } else {
+                    None
+                }
+            }
+            Filter::Not(filter) => filter.constant().map(|value| !value),
+            Filter::Condition(_) => None,
+        }
+    }
+
+    // Pushes the filter as a boolean expression, binding every value
+    pub fn push(
+        &self,
+        q_builder: &mut QueryBuilder<'_, Postgres>,
+        schema: &TableSchema,
+    ) -> Result<(), AppError> {
+        match self {
+            Filter::And(filters) => Self::push_group(q_builder, schema, filters, " AND ", "TRUE"),
+            Filter::Or(filters) => Self::push_group(q_builder, schema, filters, " OR ", "FALSE"),
+            Filter::Not(filter) => {
+                q_builder.push("NOT (");
+                filter.push(q_builder, schema)?;
+                q_builder.push(")");
+
+                Ok(())
+            }
+            Filter::Condition(condition) => condition.push(q_builder, schema),
+        }
+    }
+
+    fn push_group(
+        q_builder: &mut QueryBuilder<'_, Postgres>,
+        schema: &TableSchema,
+        filters: &[Filter],
+        separator: &str,
+        empty: &str,
+    ) -> Result<(), AppError> {
+        if filters.is_empty() {
+            q_builder.push(empty);
+
+            return Ok(());
+        }
+
+        q_builder.push("(");
+
+        for (i, filter) in filters.iter().enumerate() {
+            if i > 0 {
+                q_builder.push(separator);
+            }
+
+            filter.push(q_builder, schema)?;
+        }
+
+        q_builder.push(")");
+
+        Ok(())
+    }
+}
+
+impl Condition {
+    fn push(
+        &self,
+        q_builder: &mut QueryBuilder<'_, Postgres>,
+        schema: &TableSchema,
+    ) -> Result<(), AppError> {
+        let column = schema.column(&self.column)?;
+        let ident = column.ident();
+
+        match self.op {
+            Operator::Eq
+            | Operator::Neq
+            | Operator::Lt
+            | Operator::Lte
+            | Operator::Gt
+            | Operator::Gte => {
+                if self.value.is_null() {
+                    return Err(self.invalid("a non-null value"));
+                }
+
+                let op = match self.op {
+                    Operator::Eq => "=",
+                    Operator::Neq => "<>",
+                    Operator::Lt => "<",
+                    Operator::Lte => "<=",
+                    Operator::Gt => ">",
+                    _ => ">=",
+                };
+
+                q_builder.push(format_args!("{} {} ", ident, op));
+                utils::push_bind_value(q_builder, &self.value, column);
+            }
+            Operator::In | Operator::NotIn => {
+                let values = self
+                    .value
+                    .as_array()
+                    .ok_or_else(|| self.invalid("an array"))?;
+
+                // `x IN ()` is not valid SQL
+                if values.is_empty() {
+                    q_builder.push(match self.op {
+                        Operator::In => "FALSE",
+                        _ => "TRUE",
+                    });
+
+                    return Ok(());
+                }
+
+                q_builder.push(match self.op {
+                    Operator::In => format!("{} IN (", ident),
+                    _ => format!("{} NOT IN (", ident),
+                });
+
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        q_builder.push(", ");
+                    }
+
+                    utils::push_bind_value(q_builder, value, column);
+                }
+
+                q_builder.push(")");
+            }
+            Operator::Like | Operator::Ilike => {
+                let pattern = self
+                    .value
+                    .as_str()
+                    .ok_or_else(|| self.invalid("a string pattern"))?;
+
+                let op = match self.op {
+                    Operator::Like => "LIKE",
+                    _ => "ILIKE",
+                };
+
+                q_builder.push(format_args!("{}::text {} ", ident, op));
+                q_builder.push_bind(pattern.to_string());
+            }
+            Operator::IsNull => {
+                q_builder.push(format_args!("{} IS NULL", ident));
+            }
+            Operator::IsNotNull => {
+                q_builder.push(format_args!("{} IS NOT NULL", ident));
+            }
+            Operator::Between => {
+                let bounds = self
+                    .value
+                    .as_array()
+                    .filter(|bounds| bounds.len() == 2)
+                    .ok_or_else(|| self.invalid("an array of two values"))?;
+
+                q_builder.push(format_args!("{} BETWEEN ", ident));
+                utils::push_bind_value(q_builder, &bounds[0], column);
+                q_builder.push(" AND ");
+                utils::push_bind_value(q_builder, &bounds[1], column);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn invalid(&self, expected: &str) -> AppError {
+        AppError::new(
+            StatusCode::BAD_REQUEST,
+            format!(
+                "Filter on `{}` with `{:?}` expects {}.",
+                self.column, self.op, expected
+            ),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(json: &str) -> Filter {
+        Filter::from_query(json).unwrap()
+    }
+
+    #[test]
+    fn empty_groups_match_every_row() {
+        assert!(filter(r#"{ "and": [] }"#).matches_every_row());
+        assert!(filter(r#"{ "not": { "or": [] } }"#).matches_every_row());
+        assert!(
+            filter(r#"{ "and": [{ "and": [] }, { "not": { "or": [] } }] }"#).matches_every_row()
+        );
+        assert!(
+            filter(r#"{ "or": [{ "column": "id", "op": "eq", "value": 1 }, { "and": [] }] }"#)
+                .matches_every_row()
+        );
+    }
+
+    #[test]
+    fn conditions_filter_rows() {
+        assert!(!filter(r#"{ "column": "id", "op": "eq", "value": 1 }"#).matches_every_row());
+        assert!(!filter(r#"{ "or": [] }"#).matches_every_row());
+        assert!(!filter(
+            r#"{ "and": [{ "column": "id", "op": "eq", "value": 1 }, { "and": [] }] }"#
+        )
+        .matches_every_row());
+        assert!(!filter(r#"{ "not": { "and": [] } }"#).matches_every_row());
+    }
+}
@@ -1,4 +1,13 @@
+pub mod alter;
+pub mod api_key;
+pub mod auth;
 pub mod column;
+pub mod extract;
+pub mod filter;
+pub mod pagination;
+pub mod permission;
+pub mod policy;
 pub mod row;
 pub mod table;
 pub mod user;
+pub mod validation;
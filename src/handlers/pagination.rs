@@ -0,0 +1,356 @@
+use axum::http::StatusCode;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{Postgres, QueryBuilder};
+
+use crate::{
+    error::AppError,
+    utils::{
+        self,
+        schema::{ColumnSchema, TableSchema},
+    },
+};
+
+// Response envelope for `GET /rows`
+#[derive(Debug, Serialize)]
+pub struct Page {
+    pub data: Vec<Value>,
+    // Number of rows matching the filter, ignoring pagination
+    pub total: i64,
+    // Pass as `cursor` to fetch the next page, null if there is no way to continue by cursor
+    pub next_cursor: Option<String>,
+    pub has_more: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Order {
+    Ascending,
+    Descending,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Nulls {
+    First,
+    Last,
+}
+
+impl Order {
+    // "ASC" or "DESC", case insensitive
+    pub fn parse(order: &str) -> Result<Self, AppError> {
+        match order.to_uppercase().as_str() {
+            "ASC" => Ok(Order::Ascending),
+            "DESC" => Ok(Order::Descending),
+            _ => Err(AppError::new(
+                StatusCode::BAD_REQUEST,
+                format!("Invalid order: `{}`", order),
+            )),
+        }
+    }
+}
+
+// A column the rows are ordered by
+#[derive(Debug, Clone)]
+pub struct SortKey {
+    pub column: ColumnSchema,
+    pub order: Order,
+    pub nulls: Nulls,
+}
+
+impl SortKey {
+    // Postgres puts NULLs last when ascending and first when descending
+    pub fn new(column: ColumnSchema, order: Order) -> Self {
+        let nulls = match order {
+            Order::Ascending => Nulls::Last,
+            Order::Descending => Nulls::First,
+        };
+
+        Self {
+            column,
+            order,
+            nulls,
+        }
+    }
+
+    // Parses a sort spec such as `-created_at,title.nulls_first`.
+    // A leading `-` sorts descending, a `.nulls_first` or `.nulls_last` suffix
+    // overrides where NULLs go.
+    pub fn parse_list(spec: &str, schema: &TableSchema) -> Result<Vec<Self>, AppError> {
+        let mut keys: Vec<Self> = Vec::new();
+
+        for item in spec.split(',').map(str::trim) {
+            let (name, order) = match item.strip_prefix('-') {
+                Some(name) => (name, Order::Descending),
+                None => (item.strip_prefix('+').unwrap_or(item), Order::Ascending),
+            };
+
+            let (name, nulls) = match name.split_once('.') {
+                Some((name, "nulls_first")) => (name, Some(Nulls::First)),
+                Some((name, "nulls_last")) => (name, Some(Nulls::Last)),
+                Some(_) => {
+                    return Err(AppError::new(
+                        StatusCode::BAD_REQUEST,
+                        format!("Invalid sort: `{}`", item),
+                    ))
+                }
+                None => (name, None),
+            };
+
+            if keys.iter().any(|key| key.column.name == name) {
+                return Err(AppError::new(
+                    StatusCode::BAD_REQUEST,
+                    format!("Column `{}` is sorted more than once.", name),
+                ));
+            }
+
+            let mut key = Self::new(schema.column(name)?.clone(), order);
+
+            if let Some(nulls) = nulls {
+                key.nulls = nulls;
+            }
+
+            keys.push(key);
+        }
+
+        Ok(keys)
+    }
+
+    pub fn push(&self, q_builder: &mut QueryBuilder<'_, Postgres>) {
+        q_builder.push(format_args!(
+            "{} {} NULLS {}",
+            self.column.ident(),
+            match self.order {
+                Order::Ascending => "ASC",
+                Order::Descending => "DESC",
+            },
+            match self.nulls {
+                Nulls::First => "FIRST",
+                Nulls::Last => "LAST",
+            },
+        ));
+    }
+
+    // Pushes a condition matching rows that sort strictly after `value` on this key
+    fn push_after(&self, q_builder: &mut QueryBuilder<'_, Postgres>, value: &Value) {
+        let ident = self.column.ident();
+
+        match (value.is_null(), self.nulls) {
+            (true, Nulls::First) => {
+                q_builder.push(format_args!("{} IS NOT NULL", ident));
+            }
+            (true, Nulls::Last) => {
+                q_builder.push("FALSE");
+            }
+            (false, nulls) => {
+                let op = match self.order {
+                    Order::Ascending => ">",
+                    Order::Descending => "<",
+                };
+
+                q_builder.push(format_args!("({} {} ", ident, op));
+                utils::push_bind_value(q_builder, value, &self.column);
+
+                if nulls == Nulls::Last {
+                    q_builder.push(format_args!(" OR {} IS NULL", ident));
+                }
+
+                q_builder.push(")");
+            }
+        }
+    }
+
+    fn push_equal(&self, q_builder: &mut QueryBuilder<'_, Postgres>, value: &Value) {
+        let ident = self.column.ident();
+
+        if value.is_null() {
+            q_builder.push(format_args!("{} IS NULL", ident));
+        } else {
+            q_builder.push(format_args!("{} = ", ident));
+            utils::push_bind_value(q_builder, value, &self.column);
+        }
+    }
+}
+
+// Position of the last row of a page, handed to clients as an opaque string
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Cursor {
+    columns: Vec<String>,
+    values: Vec<Value>,
+}
+
+impl Cursor {
+    pub fn from_row(keys: &[SortKey], row: &serde_json::Map<String, Value>) -> Self {
+        Self {
+            columns: keys.iter().map(|key| key.column.name.clone()).collect(),
+            values: keys
+                .iter()
+                .map(|key| row.get(&key.column.name).cloned().unwrap_or(Value::Null))
+                .collect(),
+        }
+    }
+
+    pub fn encode(&self) -> Result<String, AppError> {
+        Ok(URL_SAFE_NO_PAD.encode(serde_json::to_vec(self)?))
+    }
+
+    pub fn decode(cursor: &str) -> Result<Self, AppError> {
+        URL_SAFE_NO_PAD
+            .decode(cursor)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .ok_or_else(|| AppError::new(StatusCode::BAD_REQUEST, "Invalid cursor."))
+    }
+
+    // Pushes a keyset condition matching every row after the cursor:
+    // (k1 after v1) OR (k1 = v1 AND k2 after v2) OR ...
+    pub fn push(
+        &self,
+        q_builder: &mut QueryBuilder<'_, Postgres>,
+        keys: &[SortKey],
+    ) -> Result<(), AppError> {
+        let matches_keys = self.columns.len() == keys.len()
+            && self
+                .columns
+                .iter()
+                .zip(keys)
+                .all(|(name, key)| *name == key.column.name);
+
+        if !matches_keys || self.values.len() != keys.len() {
+            return Err(AppError::new(
+                StatusCode::BAD_REQUEST,
+                "Cursor does not match the requested order.",
+            ));
+        }
+
+        q_builder.push("(");
+
+        for (i, key) in keys.iter().enumerate() {
+            if i > 0 {
+                q_builder.push(" OR ");
+            }
+
+            q_builder.push("(");
+
+            for (prev, value) in keys.iter().zip(&self.values).take(i) {
+                prev.push_equal(q_builder, value);
+                q_builder.push(" AND ");
+            }
+
+            key.push_after(q_builder, &self.values[i]);
+
+            q_builder.push(")");
+        }
+
+        q_builder.push(")");
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::utils::ident::Ident;
+
+    fn column(name: &str, data_type: &str) -> ColumnSchema {
+        ColumnSchema {
+            name: name.to_string(),
+            data_type: data_type.to_string(),
+            base_type: data_type.to_string(),
+            udt_name: data_type.to_string(),
+            is_nullable: true,
+            default: None,
+            is_primary_key: false,
+            primary_key_position: None,
+        }
+    }
+
+    fn schema() -> TableSchema {
+        TableSchema {
+            name: Ident::from_catalog("posts"),
+            columns: vec![
+                column("id", "integer"),
+                column("title", "text"),
+                column("created_at", "timestamp with time zone"),
+            ],
+        }
+    }
+
+    #[test]
+    fn sort_spec() {
+        let keys = SortKey::parse_list("-created_at, +title.nulls_first,id", &schema()).unwrap();
+
+        let parsed: Vec<(&str, Order, Nulls)> = keys
+            .iter()
+            .map(|key| (key.column.name.as_str(), key.order, key.nulls))
+            .collect();
+
+        assert_eq!(
+            parsed,
+            [
+                ("created_at", Order::Descending, Nulls::First),
+                ("title", Order::Ascending, Nulls::First),
+                ("id", Order::Ascending, Nulls::Last),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_sort_specs() {
+        for spec in ["title.nulls_middle", "title,-title", "missing", ""] {
+            assert!(SortKey::parse_list(spec, &schema()).is_err(), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn cursor_round_trip() {
+        let keys = SortKey::parse_list("-created_at,id", &schema()).unwrap();
+        let row = json!({ "id": 3, "title": "x", "created_at": null });
+        let cursor = Cursor::from_row(&keys, row.as_object().unwrap());
+
+        let decoded = Cursor::decode(&cursor.encode().unwrap()).unwrap();
+
+        assert_eq!(decoded.columns, ["created_at", "id"]);
+        assert_eq!(decoded.values, [Value::Null, json!(3)]);
+    }
+
+    #[test]
+    fn invalid_cursors() {
+        let not_json = URL_SAFE_NO_PAD.encode("not json");
+
+        for cursor in ["", "!!!", not_json.as_str()] {
+            assert!(Cursor::decode(cursor).is_err(), "{}", cursor);
+        }
+    }
+
+    #[test]
+    fn cursor_condition() {
+        let keys = SortKey::parse_list("-created_at,id", &schema()).unwrap();
+        let cursor = Cursor {
+            columns: vec!["created_at".to_string(), "id".to_string()],
+            values: vec![Value::Null, json!(3)],
+        };
+        let mut q_builder: QueryBuilder<'_, Postgres> = QueryBuilder::new("");
+
+        cursor.push(&mut q_builder, &keys).unwrap();
+
+        assert_eq!(
+            q_builder.sql(),
+            r#"(("created_at" IS NOT NULL) OR ("created_at" IS NULL AND ("id" > $1::integer OR "id" IS NULL)))"#
+        );
+    }
+
+    #[test]
+    fn cursor_for_another_order() {
+        let keys = SortKey::parse_list("id", &schema()).unwrap();
+        let cursor = Cursor {
+            columns: vec!["title".to_string()],
+            values: vec![json!("x")],
+        };
+        let mut q_builder: QueryBuilder<'_, Postgres> = QueryBuilder::new("");
+
+        assert!(cursor.push(&mut q_builder, &keys).is_err());
+    }
+}
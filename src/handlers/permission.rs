@@ -0,0 +1,116 @@
+use axum::{extract::State, http::StatusCode, response::Result};
+use serde::Serialize;
+use sqlx::{prelude::FromRow, PgPool};
+
+use crate::{
+    auth::{
+        permission::{Role, TablePermissions},
+        AuthUser,
+    },
+    error::AppError,
+    utils::ident::Ident,
+};
+
+use super::extract::{Json, Path};
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct TablePermissionsEntry {
+    role: Role,
+    table_name: String,
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    permissions: TablePermissions,
+}
+
+// Every override, roles fall back to their defaults on tables that aren't listed
+pub async fn get_permissions(
+    State(pool): State<PgPool>,
+    user: AuthUser,
+) -> Result<axum::Json<Vec<TablePermissionsEntry>>, AppError> {
+    user.require_admin()?;
+
+    let entries = sqlx::query_as::<_, TablePermissionsEntry>(
+        r#"
+        SELECT role, table_name, can_read, can_insert, can_update, can_delete, can_alter
+        FROM table_permissions
+        ORDER BY table_name, role
+        "#,
+    )
+    .fetch_all(&pool)
+    .await?;
+
+    Ok(axum::Json(entries))
+}
+
+// Replaces what `role` may do on `table`
+pub async fn set_permissions(
+    State(pool): State<PgPool>,
+    user: AuthUser,
+    Path((role, table)): Path<(Role, String)>,
+    Json(permissions): Json<TablePermissions>,
+) -> Result<axum::Json<TablePermissionsEntry>, AppError> {
+    user.require_admin()?;
+
+    if role == Role::Admin {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            "Admins always have every permission.",
+        ));
+    }
+
+    let table = Ident::parse(&table)?;
+
+    let entry = sqlx::query_as::<_, TablePermissionsEntry>(
+        r#"
+        INSERT INTO table_permissions
+            (role, table_name, can_read, can_insert, can_update, can_delete, can_alter)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        ON CONFLICT (role, table_name) DO UPDATE SET
+            can_read = EXCLUDED.can_read,
+            can_insert = EXCLUDED.can_insert,
+            can_update = EXCLUDED.can_update,
+            can_delete = EXCLUDED.can_delete,
+            can_alter = EXCLUDED.can_alter
+        RETURNING role, table_name, can_read, can_insert, can_update, can_delete, can_alter
+        "#,
+    )
+    .bind(role)
+    .bind(table.as_str())
+    .bind(permissions.can_read)
+    .bind(permissions.can_insert)
+    .bind(permissions.can_update)
+    .bind(permissions.can_delete)
+    .bind(permissions.can_alter)
+    .fetch_one(&pool)
+    .await?;
+
+    Ok(axum::Json(entry))
+}
+
+// Reverts `role` to its defaults on `table`
+pub async fn delete_permissions(
+    State(pool): State<PgPool>,
+    user: AuthUser,
+    Path((role, table)): Path<(Role, String)>,
+) -> Result<StatusCode, AppError> {
+    user.require_admin()?;
+
+    let result = sqlx::query("DELETE FROM table_permissions WHERE role = $1 AND table_name = $2")
+        .bind(role)
+        .bind(&table)
+        .execute(&pool)
+        .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(AppError::new(
+            StatusCode::NOT_FOUND,
+            format!(
+                "No permissions set for `{}` on table `{}`.",
+                role.as_str(),
+                table
+            ),
+        ));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,126 @@
+use axum::{extract::State, http::StatusCode, response::Result};
+use serde::Deserialize;
+use serde_json::Value;
+use sqlx::{types::Json, PgPool, Postgres, QueryBuilder};
+use uuid::Uuid;
+
+use crate::{
+    auth::{permission::Role, policy::RowPolicy, AuthUser},
+    error::AppError,
+    utils::{ident::Ident, schema::TableSchema},
+};
+
+use super::{
+    extract::{self, Path, Query},
+    filter::Filter,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct PolicyQuery {
+    table: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewRowPolicy {
+    table: String,
+    role: Role,
+    // e.g. { "column": "author_id", "op": "eq", "value": "$current_user" }
+    filter: Value,
+    // Which statements the policy applies to, all of them by default
+    #[serde(default = "default_true")]
+    for_read: bool,
+    #[serde(default = "default_true")]
+    for_update: bool,
+    #[serde(default = "default_true")]
+    for_delete: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+pub async fn get_policies(
+    State(pool): State<PgPool>,
+    user: AuthUser,
+    Query(query): Query<PolicyQuery>,
+) -> Result<axum::Json<Vec<RowPolicy>>, AppError> {
+    user.require_admin()?;
+
+    let policies = sqlx::query_as::<_, RowPolicy>(
+        r#"
+        SELECT * FROM row_policies
+        WHERE $1::text IS NULL OR table_name = $1
+        ORDER BY table_name, created_at
+        "#,
+    )
+    .bind(query.table)
+    .fetch_all(&pool)
+    .await?;
+
+    Ok(axum::Json(policies))
+}
+
+pub async fn create_policy(
+    State(pool): State<PgPool>,
+    user: AuthUser,
+    extract::Json(policy): extract::Json<NewRowPolicy>,
+) -> Result<(StatusCode, axum::Json<RowPolicy>), AppError> {
+    user.require_admin()?;
+
+    if policy.role == Role::Admin {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            "Admins aren't restricted by row policies.",
+        ));
+    }
+
+    let schema = TableSchema::fetch(&pool, &Ident::parse(&policy.table)?).await?;
+
+    // Checks the filter's shape and columns, values are only checked when it's applied
+    let filter: Filter = serde_json::from_value(policy.filter.clone()).map_err(|err| {
+        AppError::new(StatusCode::BAD_REQUEST, format!("Invalid filter: {}", err))
+    })?;
+    let mut q_builder: QueryBuilder<'_, Postgres> = QueryBuilder::new("");
+
+    filter.push(&mut q_builder, &schema)?;
+
+    let policy = sqlx::query_as::<_, RowPolicy>(
+        r#"
+        INSERT INTO row_policies (table_name, role, filter, for_read, for_update, for_delete)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        RETURNING *
+        "#,
+    )
+    .bind(schema.name.as_str())
+    .bind(policy.role)
+    .bind(Json(&policy.filter))
+    .bind(policy.for_read)
+    .bind(policy.for_update)
+    .bind(policy.for_delete)
+    .fetch_one(&pool)
+    .await?;
+
+    Ok((StatusCode::CREATED, axum::Json(policy)))
+}
+
+pub async fn delete_policy(
+    State(pool): State<PgPool>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, AppError> {
+    user.require_admin()?;
+
+    let result = sqlx::query("DELETE FROM row_policies WHERE id = $1")
+        .bind(id)
+        .execute(&pool)
+        .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(AppError::new(
+            StatusCode::NOT_FOUND,
+            format!("Row policy `{}` not found.", id),
+        ));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
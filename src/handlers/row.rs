@@ -1,22 +1,32 @@
 use axum::{
-    extract::{Path, Query, State},
+    extract::{rejection::JsonRejection, State},
     http::StatusCode,
     response::Result,
 };
-use serde::Deserialize;
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use sqlx::{Execute, PgPool, Postgres, QueryBuilder, Row as SqlxRow};
+use sqlx::{Connection, PgPool, Postgres, QueryBuilder, Row as SqlxRow};
 use tracing::debug;
 
-use crate::{error::AppError, utils};
-
-use super::column;
+use crate::{
+    auth::{permission::Permission, AuthUser},
+    error::AppError,
+    utils::{
+        self,
+        ident::Ident,
+        schema::{ColumnSchema, TableSchema},
+    },
+};
 
-#[derive(Debug, Deserialize)]
-pub enum Order {
-    Ascending(String),
-    Descending(String),
-}
+use super::{
+    column,
+    extract::{Json, Path, Query},
+    filter::{Condition, Filter, Operator},
+    pagination::{Cursor, Order, Page, SortKey},
+    validation::{ValidJson, Validate, Validator},
+};
 
 // Json body content
 #[derive(Debug, Deserialize)]
@@ -24,276 +34,1162 @@ pub struct Row {
     table: String,
     // Defaults to '*'
     columns: Option<Vec<column::InsertOnColumn>>,
-    // For WHERE clause
-    // NOTE: Can only filter one column for now
-    filters: Option<column::InsertOnColumn>,
+    // Columns of the inserted row to send back
+    // Defaults to '*'
+    returning: Option<Vec<String>>,
+    // Turns an insert into an upsert
+    on_conflict: Option<OnConflict>,
+}
+
+impl Validate for Row {
+    fn validate(&self, validator: &mut Validator) {
+        validator.ident("table", &self.table);
+
+        if let Some(ref columns) = self.columns {
+            if columns.is_empty() {
+                validator.error("columns", "Leave out `columns` to insert only defaults.");
+            }
+
+            validator.ident_list("columns", columns.iter().map(|col| &col.name));
+        }
+
+        if let Some(ref returning) = self.returning {
+            validator.ident_list("returning", returning);
+        }
+
+        if let Some(ref on_conflict) = self.on_conflict {
+            if let Some(ref target) = on_conflict.target {
+                if target.is_empty() {
+                    validator.error("on_conflict.target", "At least one column is required.");
+                }
+
+                validator.ident_list("on_conflict.target", target);
+            }
+
+            if let Some(ref update) = on_conflict.update {
+                validator.ident_list("on_conflict.update", update);
+            }
+        }
+    }
+}
+
+// INSERT ... ON CONFLICT {target} DO UPDATE SET {update} = EXCLUDED.{update}
+#[derive(Debug, Deserialize)]
+pub struct OnConflict {
+    // Defaults to the primary key, or the first unique constraint if there is none
+    target: Option<Vec<String>>,
+    #[serde(default)]
+    action: ConflictAction,
+    // Columns overwritten with the new values on conflict
+    // Defaults to every inserted column that isn't part of the target
+    update: Option<Vec<String>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictAction {
+    #[default]
+    Update,
+    Nothing,
 }
 
 // `Cs` stands for "Comma Separated"
 type CsString = String;
 
-// /contents/:id?table={table}&columns={columns}&limit={limit}&order_by={order_by}&order={order}
+// /contents/:id?table={table}&columns={columns}&filter={filter}&limit={limit}&offset={offset}&cursor={cursor}&sort={sort}
 #[derive(Debug, Deserialize)]
 pub struct SelectQuery {
     table: String,
     // Comma separated column/s
     columns: Option<CsString>,
+    // JSON encoded `Filter`
+    filter: Option<String>,
     limit: Option<i64>,
-    // Comma separated column/s to order by
+    offset: Option<i64>,
+    // `next_cursor` of the previous page, can't be combined with `offset`
+    cursor: Option<String>,
+    // Comma separated column/s to sort by, e.g. `-created_at,title.nulls_first`
+    // See `SortKey::parse_list`
+    sort: Option<CsString>,
+    // Comma separated column/s to order by, superseded by `sort`
     order_by: Option<CsString>,
-    // ASC or DESC
+    // ASC or DESC, applies to every `order_by` column
     // ASC by default
     order: Option<String>,
 }
 
-// SELECT * FROM {table} WHERE {conditions} ORDER BY {order} LIMIT {limit}
+// SELECT * FROM {table} WHERE {conditions} ORDER BY {order} LIMIT {limit} OFFSET {offset}
+// The filter can be passed in the query string, as a JSON body, or both
 pub async fn select_many(
     State(pool): State<PgPool>,
+    user: AuthUser,
     Query(query): Query<SelectQuery>,
-) -> Result<(StatusCode, axum::Json<Value>), AppError> {
-    let sql = query.push_select().order().limit().sql();
+    body: Result<axum::Json<Filter>, JsonRejection>,
+) -> Result<(StatusCode, axum::Json<Page>), AppError> {
+    let mut filters: Vec<Filter> = Vec::new();
 
-    debug!("{}", sql);
+    if let Some(ref filter) = query.filter {
+        filters.push(Filter::from_query(filter)?);
+    }
 
-    let pg_rows = sqlx::query(sql.as_str()).fetch_all(&pool).await?;
-    let mut json_vec: Vec<Value> = Vec::new();
-    let mut json_map = serde_json::Map::new();
+    match body {
+        Ok(axum::Json(filter)) => filters.push(filter),
+        // No body was sent
+        Err(JsonRejection::MissingJsonContentType(_)) => {}
+        Err(rejection) => return Err(rejection.into()),
+    }
+
+    let name = Ident::parse(&query.table)?;
+
+    user.authorize(&pool, &name, Permission::Read).await?;
+
+    filters.extend(user.row_policy(&pool, &name, Permission::Read).await?);
+
+    let filter = Filter::all_of(filters);
+
+    let schema = TableSchema::fetch(&pool, &name).await?;
+
+    let mut count = push_count(&schema, filter.as_ref())?;
+
+    debug!("{}", count.sql());
+
+    let total: i64 = count.build_query_scalar().fetch_one(&pool).await?;
+
+    let mut select = query.push_select(schema)?;
+
+    select
+        .conditions(filter.as_ref())?
+        .after()?
+        .order()
+        .limit()
+        .offset();
+
+    debug!("{}", select.sql());
+
+    let pg_rows = select.builder.build().fetch_all(&pool).await?;
+    let mut json_maps: Vec<serde_json::Map<String, Value>> = Vec::new();
 
     for row in pg_rows.iter() {
-        let cols = row.columns();
+        let mut json_map = serde_json::Map::new();
+
+        utils::insert_col_to_map(row, row.columns(), &mut json_map);
 
-        utils::insert_col_to_map(row, cols, &mut json_map);
+        json_maps.push(json_map);
+    }
 
-        let json = serde_json::to_value(&json_map)?;
+    // One extra row is fetched to know if there is a next page
+    let has_more = select
+        .limit
+        .is_some_and(|limit| json_maps.len() as i64 > limit);
 
-        json_vec.push(json);
-        json_map.clear();
+    if has_more {
+        json_maps.pop();
     }
 
-    let json: Value = serde_json::to_value(&json_vec)?;
+    let next_cursor = match json_maps.last() {
+        Some(last) if has_more && !select.keys.is_empty() => {
+            Some(Cursor::from_row(&select.keys, last).encode()?)
+        }
+        _ => None,
+    };
+
+    let data = json_maps
+        .into_iter()
+        .map(|mut json_map| {
+            select.hidden.iter().for_each(|name| {
+                json_map.remove(name);
+            });
+
+            Value::Object(json_map)
+        })
+        .collect();
 
-    Ok((StatusCode::OK, axum::Json(json)))
+    let page = Page {
+        data,
+        total,
+        next_cursor,
+        has_more,
+    };
+
+    Ok((StatusCode::OK, axum::Json(page)))
 }
 
-// SELECT * FROM {table} WHERE {conditions}
+// SELECT COUNT(*) FROM {table} WHERE {conditions}
+fn push_count(
+    schema: &TableSchema,
+    filter: Option<&Filter>,
+) -> Result<QueryBuilder<'static, Postgres>, AppError> {
+    let mut q_builder: QueryBuilder<'_, Postgres> = QueryBuilder::new("SELECT COUNT(*) FROM ");
+
+    q_builder.push(&schema.name);
+
+    if let Some(filter) = filter {
+        q_builder.push(" WHERE ");
+
+        filter.push(&mut q_builder, schema)?;
+    }
+
+    Ok(q_builder)
+}
+
+// SELECT * FROM {table} WHERE {primary key} = {id}
+// Composite keys are passed as comma separated values in key order, e.g. /rows/42,en
+// A literal comma or backslash inside a value is escaped with a backslash
 pub async fn select_one(
     State(pool): State<PgPool>,
+    user: AuthUser,
     Path(id): Path<String>,
     Query(query): Query<SelectQuery>,
 ) -> Result<(StatusCode, axum::Json<Value>), AppError> {
-    let sql = query.push_select().filter(id).sql();
-
-    debug!("{}", sql);
-
-    let pg_row = sqlx::query(sql.as_str()).fetch_one(&pool).await?;
+    let name = Ident::parse(&query.table)?;
+
+    user.authorize(&pool, &name, Permission::Read).await?;
+
+    let policy = user.row_policy(&pool, &name, Permission::Read).await?;
+    let schema = TableSchema::fetch(&pool, &name).await?;
+    let mut select = query.push_select(schema)?;
+
+    select.primary_key(&id)?.conditions(policy.as_ref())?;
+
+    debug!("{}", select.sql());
+
+    let pg_row = select
+        .builder
+        .build()
+        .fetch_optional(&pool)
+        .await?
+        .ok_or_else(|| {
+            AppError::new(
+                StatusCode::NOT_FOUND,
+                format!(
+                    "No row in `{}` with primary key `{}`.",
+                    select.schema.name.as_str(),
+                    id
+                ),
+            )
+        })?;
     let mut json_map = serde_json::Map::new();
 
     utils::insert_col_to_map(&pg_row, pg_row.columns(), &mut json_map);
 
+    select.hidden.iter().for_each(|name| {
+        json_map.remove(name);
+    });
+
     let json: Value = serde_json::to_value(&json_map)?;
 
     Ok((StatusCode::OK, axum::Json(json)))
 }
 
-// INSERT INTO {table} {rows} VALUES {values}
+// INSERT INTO {table} {rows} VALUES {values} ON CONFLICT {on_conflict} RETURNING {returning}
+// Upserts respond with 200 and a null body when the conflicting row was left untouched
 pub async fn insert(
     State(pool): State<PgPool>,
-    axum::Json(row): axum::Json<Row>,
-) -> Result<StatusCode, AppError> {
-    let sql = row.push_insert();
+    user: AuthUser,
+    ValidJson(row): ValidJson<Row>,
+) -> Result<(StatusCode, axum::Json<Value>), AppError> {
+    let name = Ident::parse(&row.table)?;
+
+    user.authorize(&pool, &name, Permission::Insert).await?;
+
+    // Upserts overwrite existing rows
+    if let Some(OnConflict {
+        action: ConflictAction::Update,
+        ..
+    }) = row.on_conflict
+    {
+        user.authorize(&pool, &name, Permission::Update).await?;
+    }
+
+    let schema = TableSchema::fetch(&pool, &name).await?;
+
+    let (unique_keys, policy) = match row.on_conflict {
+        Some(_) => (
+            schema.unique_keys(&pool).await?,
+            user.row_policy(&pool, &name, Permission::Update).await?,
+        ),
+        None => (Vec::new(), None),
+    };
 
-    debug!("{}", sql);
+    let mut q_builder = row.push_insert(&schema, &unique_keys, policy.as_ref())?;
 
-    sqlx::query(sql.as_str()).execute(&pool).await?;
+    debug!("{}", q_builder.sql());
 
-    Ok(StatusCode::CREATED)
+    let pg_row = q_builder.build().fetch_optional(&pool).await?;
+
+    match (pg_row, row.on_conflict) {
+        (Some(pg_row), None) => Ok((StatusCode::CREATED, axum::Json(utils::row_to_json(&pg_row)))),
+        (Some(pg_row), Some(_)) => Ok((StatusCode::OK, axum::Json(utils::row_to_json(&pg_row)))),
+        (None, _) => Ok((StatusCode::OK, axum::Json(Value::Null))),
+    }
 }
 
-// UPDATE {table} SET {row} = {value}, {row} = {value} WHERE {row} = {value}
-pub async fn update(
+// Postgres accepts at most 65535 bind parameters per statement
+const MAX_BINDS: usize = 65535;
+
+#[derive(Debug, Deserialize)]
+pub struct BulkInsert {
+    table: String,
+    // Each object maps column names to values, missing columns get their DEFAULT
+    rows: Vec<serde_json::Map<String, Value>>,
+    // All-or-nothing when true, otherwise failed rows are skipped and reported
+    #[serde(default = "default_atomic")]
+    atomic: bool,
+}
+
+fn default_atomic() -> bool {
+    true
+}
+
+#[derive(Debug, Serialize)]
+pub struct BulkInsertResult {
+    inserted: u64,
+    errors: Vec<BulkInsertError>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BulkInsertError {
+    // Position of the row in the request
+    index: usize,
+    #[serde(flatten)]
+    error: AppError,
+}
+
+// INSERT INTO {table} {columns} VALUES {values}, {values}, ...
+pub async fn insert_many(
     State(pool): State<PgPool>,
-    axum::Json(row): axum::Json<Row>,
-) -> Result<StatusCode, AppError> {
-    let sql = row.push_update();
+    user: AuthUser,
+    Json(bulk): Json<BulkInsert>,
+) -> Result<(StatusCode, axum::Json<BulkInsertResult>), AppError> {
+    let name = Ident::parse(&bulk.table)?;
+
+    user.authorize(&pool, &name, Permission::Insert).await?;
+
+    let schema = TableSchema::fetch(&pool, &name).await?;
+    let columns = bulk.columns(&schema)?;
+
+    let mut txn = pool.begin().await?;
+    let mut result = BulkInsertResult {
+        inserted: 0,
+        errors: Vec::new(),
+    };
+
+    if bulk.atomic {
+        let chunk_size = MAX_BINDS / columns.len();
+
+        for chunk in bulk.rows.chunks(chunk_size) {
+            let mut q_builder = BulkInsert::push_insert(&schema, &columns, chunk);
+
+            debug!("{}", q_builder.sql());
+
+            result.inserted += q_builder.build().execute(&mut *txn).await?.rows_affected();
+        }
+    } else {
+        // Each row gets its own savepoint so a failure doesn't abort the transaction
+        for (index, row) in bulk.rows.iter().enumerate() {
+            let mut q_builder =
+                BulkInsert::push_insert(&schema, &columns, std::slice::from_ref(row));
+            let mut savepoint = txn.begin().await?;
+
+            match q_builder.build().execute(&mut *savepoint).await {
+                Ok(done) => {
+                    savepoint.commit().await?;
+                    result.inserted += done.rows_affected();
+                }
+                Err(err) => {
+                    savepoint.rollback().await?;
+                    result.errors.push(BulkInsertError {
+                        index,
+                        error: err.into(),
+                    });
+                }
+            }
+        }
+    }
+
+    txn.commit().await?;
+
+    let code = if result.errors.is_empty() {
+        StatusCode::CREATED
+    } else {
+        StatusCode::MULTI_STATUS
+    };
+
+    Ok((code, axum::Json(result)))
+}
+
+impl BulkInsert {
+    // Every column used by at least one row, in order of first appearance
+    fn columns<'a>(&self, schema: &'a TableSchema) -> Result<Vec<&'a ColumnSchema>, AppError> {
+        if self.rows.is_empty() {
+            return Err(AppError::new(
+                StatusCode::BAD_REQUEST,
+                "At least one row is required.",
+            ));
+        }
+
+        let mut columns: Vec<&ColumnSchema> = Vec::new();
+
+        for name in self.rows.iter().flat_map(|row| row.keys()) {
+            if !columns.iter().any(|col| col.name == *name) {
+                columns.push(schema.column(name)?);
+            }
+        }
+
+        if columns.is_empty() {
+            return Err(AppError::new(
+                StatusCode::BAD_REQUEST,
+                "Rows must set at least one column.",
+            ));
+        }
+
+        Ok(columns)
+    }
+
+    fn push_insert(
+        schema: &TableSchema,
+        columns: &[&ColumnSchema],
+        rows: &[serde_json::Map<String, Value>],
+    ) -> QueryBuilder<'static, Postgres> {
+        let mut q_builder: QueryBuilder<'_, Postgres> = QueryBuilder::new("INSERT INTO ");
+
+        q_builder.push(&schema.name);
+        q_builder.push(" (");
+
+        let mut comma_sep = q_builder.separated(", ");
+
+        columns.iter().for_each(|col| {
+            comma_sep.push(col.ident());
+        });
 
-    debug!("{}", sql);
+        q_builder.push(") VALUES ");
 
-    sqlx::query(sql.as_str()).execute(&pool).await?;
+        for (i, row) in rows.iter().enumerate() {
+            if i > 0 {
+                q_builder.push(", ");
+            }
+
+            q_builder.push("(");
+
+            for (j, col) in columns.iter().enumerate() {
+                if j > 0 {
+                    q_builder.push(", ");
+                }
+
+                match row.get(&col.name) {
+                    Some(value) => utils::push_bind_value(&mut q_builder, value, col),
+                    None => {
+                        q_builder.push("DEFAULT");
+                    }
+                }
+            }
+
+            q_builder.push(")");
+        }
 
-    Ok(StatusCode::OK)
+        q_builder
+    }
 }
 
 #[derive(Debug, Deserialize)]
-pub struct DeleteRow {
+pub struct UpdateRows {
     table: String,
-    pkey_column: String,
-    values: Vec<Value>,
+    // Column name to its new value
+    set: BTreeMap<String, column::Assignment>,
+    filter: Option<Filter>,
+    // Must be true to update without a filter
+    #[serde(default)]
+    all: bool,
+    // Columns of the updated rows to send back
+    // Defaults to '*'
+    returning: Option<Vec<String>>,
 }
 
-// DELETE FROM {table} WHERE id IN ({id})
-pub async fn delete(
-    State(pool): State<PgPool>,
-    axum::Json(row): axum::Json<DeleteRow>,
-) -> Result<StatusCode, AppError> {
-    let mut q_builder: QueryBuilder<'_, Postgres> = QueryBuilder::new("DELETE FROM ");
-    q_builder.push(row.table);
-    q_builder.push(format_args!(" WHERE {} IN (", row.pkey_column));
+impl Validate for UpdateRows {
+    fn validate(&self, validator: &mut Validator) {
+        validator.ident("table", &self.table);
+
+        if self.set.is_empty() {
+            validator.error("set", "At least one column must be set.");
+        }
+
+        for (name, assignment) in self.set.iter() {
+            let field = format!("set.{}", name);
 
-    let mut comma_sep = q_builder.separated(", ");
+            validator.ident(field.clone(), name);
 
-    row.values.iter().for_each(|pkey| match pkey {
-        Value::String(s) => {
-            comma_sep.push(format_args!("'{}'", s));
+            match assignment {
+                column::Assignment::Increment(amount) if !amount.is_number() => {
+                    validator.error(format!("{}.increment", field), "Must be a number.");
+                }
+                column::Assignment::Expression(expression)
+                    if expression.as_str().and_then(utils::db_expression).is_none() =>
+                {
+                    validator.error(
+                        format!("{}.expression", field),
+                        format!("Unsupported database expression: {}", expression),
+                    );
+                }
+                _ => {}
+            }
         }
-        _ => {
-            comma_sep.push(pkey);
+
+        match self.filter {
+            None if !self.all => {
+                validator.error("all", "Must be true to update every row without a filter.");
+            }
+            Some(ref filter) if !self.all && filter.matches_every_row() => {
+                validator.error(
+                    "filter",
+                    "Matches every row, pass `all: true` to update them all.",
+                );
+            }
+            _ => {}
         }
-    });
 
-    comma_sep.push_unseparated(")");
+        if let Some(ref returning) = self.returning {
+            validator.ident_list("returning", returning);
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct AffectedRows {
+    affected: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rows: Option<Vec<Value>>,
+}
+
+// UPDATE {table} SET {column} = {assignment}, ... WHERE {conditions} RETURNING {returning}
+pub async fn update(
+    State(pool): State<PgPool>,
+    user: AuthUser,
+    ValidJson(update): ValidJson<UpdateRows>,
+) -> Result<(StatusCode, axum::Json<AffectedRows>), AppError> {
+    let name = Ident::parse(&update.table)?;
+
+    user.authorize(&pool, &name, Permission::Update).await?;
 
-    let sql = q_builder.build().sql();
+    let policy = user.row_policy(&pool, &name, Permission::Update).await?;
+    let schema = TableSchema::fetch(&pool, &name).await?;
+    let mut q_builder = update.push_update(&schema, policy)?;
 
-    debug!("{}", sql);
+    debug!("{}", q_builder.sql());
 
-    sqlx::query(sql).execute(&pool).await?;
+    let pg_rows = q_builder.build().fetch_all(&pool).await?;
 
-    Ok(StatusCode::NO_CONTENT)
+    let affected = AffectedRows {
+        affected: pg_rows.len(),
+        rows: Some(pg_rows.iter().map(utils::row_to_json).collect()),
+    };
+
+    Ok((StatusCode::OK, axum::Json(affected)))
 }
 
-impl Row {
-    fn push_update(&self) -> String {
+// Types that support `+` with a number
+const NUMERIC_TYPES: &[&str] = &["int2", "int4", "int8", "float4", "float8", "numeric"];
+
+impl UpdateRows {
+    // Only rows matching `policy` are updated, on top of the filter
+    fn push_update(
+        &self,
+        schema: &TableSchema,
+        policy: Option<Filter>,
+    ) -> Result<QueryBuilder<'static, Postgres>, AppError> {
+        if self.set.is_empty() {
+            return Err(AppError::new(
+                StatusCode::BAD_REQUEST,
+                "At least one column must be set.",
+            ));
+        }
+
+        if !self.all && self.filter.as_ref().is_none_or(Filter::matches_every_row) {
+            return Err(AppError::new(
+                StatusCode::BAD_REQUEST,
+                "Refusing to update every row without `all: true`.",
+            ));
+        }
+
         let mut q_builder: QueryBuilder<'_, Postgres> = QueryBuilder::new("UPDATE ");
 
-        q_builder.push(self.table.as_str());
+        q_builder.push(&schema.name);
+        q_builder.push(" SET ");
 
-        if let (Some(columns), Some(filters)) = (self.columns.as_ref(), self.filters.as_ref()) {
-            let mut comma_sep = q_builder.separated(", ");
+        for (i, (name, assignment)) in self.set.iter().enumerate() {
+            if i > 0 {
+                q_builder.push(", ");
+            }
 
-            comma_sep.push_unseparated(" SET ");
+            let column = schema.column(name)?;
+            let ident = column.ident();
 
-            columns.iter().for_each(|col| {
-                comma_sep.push_unseparated(format_args!("{} = ", col.name));
+            q_builder.push(format_args!("{} = ", ident));
 
-                match &col.value {
-                    Value::String(s) => {
-                        if s == "gen_random_uuid()" || s == "now()" {
-                            comma_sep.push(s);
-                        } else {
-                            comma_sep.push(format_args!("'{}'", s));
-                        }
-                    }
-                    _ => {
-                        comma_sep.push(&filters.value);
+            match assignment {
+                column::Assignment::Value(value) => {
+                    utils::push_bind_value(&mut q_builder, value, column);
+                }
+                column::Assignment::Increment(amount) => {
+                    if !amount.is_number() || !NUMERIC_TYPES.contains(&column.udt_name.as_str()) {
+                        return Err(AppError::new(
+                            StatusCode::BAD_REQUEST,
+                            format!("Column `{}` can't be incremented by {}.", name, amount),
+                        ));
                     }
+
+                    q_builder.push(format_args!("{} + ", ident));
+                    utils::push_bind_value(&mut q_builder, amount, column);
                 }
-            });
+                column::Assignment::Expression(expression) => {
+                    utils::push_db_expression(&mut q_builder, expression)?;
+                }
+            }
+        }
+
+        if let Some(filter) = Filter::all_of(self.filter.clone().into_iter().chain(policy)) {
+            q_builder.push(" WHERE ");
 
-            self.push_filter(&mut q_builder);
+            filter.push(&mut q_builder, schema)?;
         }
 
-        q_builder.build().sql().to_string()
+        push_returning(&mut q_builder, schema, self.returning.as_deref())?;
+
+        Ok(q_builder)
     }
+}
 
-    fn push_insert(&self) -> String {
-        let mut q_builder: QueryBuilder<'_, Postgres> = QueryBuilder::new("INSERT INTO ");
+#[derive(Debug, Deserialize)]
+pub struct DeleteRow {
+    table: String,
+    // Defaults to the table's primary key
+    pkey_column: Option<String>,
+    // Primary key values of the rows to delete
+    // Each value is an array in key order when the primary key is composite
+    values: Option<Vec<Value>>,
+    // Used instead of `values`
+    filter: Option<Filter>,
+    // Must be true to delete without `values` or a filter
+    #[serde(default)]
+    all: bool,
+    // Columns of the deleted rows to send back
+    // Only the count is sent when not given
+    returning: Option<Vec<String>>,
+}
 
-        q_builder.push(self.table.as_str());
+impl Validate for DeleteRow {
+    fn validate(&self, validator: &mut Validator) {
+        validator.ident("table", &self.table);
 
-        if let Some(ref columns) = self.columns {
-            let mut comma_sep = q_builder.separated(", ");
+        if let Some(ref pkey_column) = self.pkey_column {
+            validator.ident("pkey_column", pkey_column);
+        }
 
-            comma_sep.push_unseparated(" (");
+        match (&self.values, &self.filter) {
+            (Some(_), Some(_)) => {
+                validator.error("values", "Pass either `values` or `filter`, not both.");
+            }
+            (Some(values), None) if values.is_empty() => {
+                validator.error("values", "At least one value is required.");
+            }
+            (None, None) if !self.all => {
+                validator.error(
+                    "all",
+                    "Must be true to delete every row without `values` or a filter.",
+                );
+            }
+            (None, Some(filter)) if !self.all && filter.matches_every_row() => {
+                validator.error(
+                    "filter",
+                    "Matches every row, pass `all: true` to delete them all.",
+                );
+            }
+            _ => {}
+        }
 
-            columns.iter().for_each(|col| {
-                comma_sep.push(&col.name);
-            });
+        if let Some(ref returning) = self.returning {
+            validator.ident_list("returning", returning);
+        }
+    }
+}
+
+// DELETE FROM {table} WHERE {conditions} RETURNING {returning}
+pub async fn delete(
+    State(pool): State<PgPool>,
+    user: AuthUser,
+    ValidJson(row): ValidJson<DeleteRow>,
+) -> Result<(StatusCode, axum::Json<AffectedRows>), AppError> {
+    let name = Ident::parse(&row.table)?;
 
-            comma_sep.push_unseparated(") VALUES (");
+    user.authorize(&pool, &name, Permission::Delete).await?;
 
-            let mut comma_sep = q_builder.separated(", ");
+    let policy = user.row_policy(&pool, &name, Permission::Delete).await?;
+    let schema = TableSchema::fetch(&pool, &name).await?;
+    let mut q_builder = row.push_delete(&schema, policy)?;
+
+    debug!("{}", q_builder.sql());
+
+    let affected = match row.returning {
+        Some(_) => {
+            let pg_rows = q_builder.build().fetch_all(&pool).await?;
+
+            AffectedRows {
+                affected: pg_rows.len(),
+                rows: Some(pg_rows.iter().map(utils::row_to_json).collect()),
+            }
+        }
+        None => AffectedRows {
+            affected: q_builder.build().execute(&pool).await?.rows_affected() as usize,
+            rows: None,
+        },
+    };
+
+    Ok((StatusCode::OK, axum::Json(affected)))
+}
+
+impl DeleteRow {
+    // Only rows matching `policy` are deleted, on top of the keys or filter
+    fn push_delete(
+        &self,
+        schema: &TableSchema,
+        policy: Option<Filter>,
+    ) -> Result<QueryBuilder<'static, Postgres>, AppError> {
+        let filter = match (&self.values, &self.filter) {
+            (Some(_), Some(_)) => {
+                return Err(AppError::new(
+                    StatusCode::BAD_REQUEST,
+                    "Pass either `values` or `filter`, not both.",
+                ))
+            }
+            (Some(values), None) => Some(self.primary_key_filter(schema, values)?),
+            (None, Some(filter)) if self.all || !filter.matches_every_row() => Some(filter.clone()),
+            (None, None) if self.all => None,
+            (None, _) => {
+                return Err(AppError::new(
+                    StatusCode::BAD_REQUEST,
+                    "Refusing to delete every row without `all: true`.",
+                ))
+            }
+        };
+
+        let mut q_builder: QueryBuilder<'_, Postgres> = QueryBuilder::new("DELETE FROM ");
+
+        q_builder.push(&schema.name);
+
+        if let Some(filter) = Filter::all_of(filter.into_iter().chain(policy)) {
+            q_builder.push(" WHERE ");
+
+            filter.push(&mut q_builder, schema)?;
+        }
+
+        if self.returning.is_some() {
+            push_returning(&mut q_builder, schema, self.returning.as_deref())?;
+        }
+
+        Ok(q_builder)
+    }
 
-            columns
+    // {pkey} IN ({values}), or (k1 = v1 AND k2 = v2) OR ... for composite keys
+    fn primary_key_filter(
+        &self,
+        schema: &TableSchema,
+        values: &[Value],
+    ) -> Result<Filter, AppError> {
+        let columns: Vec<String> = match self.pkey_column {
+            Some(ref name) => vec![schema.column(name)?.name.clone()],
+            None => schema
+                .primary_key()
                 .iter()
-                .for_each(|col| match (&col.value, &col.is_db_expression) {
-                    (Value::String(s), true) => {
-                        comma_sep.push(s);
-                    }
-                    (Value::String(s), false) => {
-                        comma_sep.push(format_args!("'{}'", s));
-                    }
-                    _ => {
-                        comma_sep.push(&col.value);
-                    }
+                .map(|col| col.name.clone())
+                .collect(),
+        };
+
+        match columns.as_slice() {
+            [] => Err(AppError::new(
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "Table `{}` has no primary key, pass `pkey_column` or a filter.",
+                    schema.name.as_str()
+                ),
+            )),
+            [column] => Ok(Filter::Condition(Condition {
+                column: column.clone(),
+                op: Operator::In,
+                value: Value::Array(values.to_vec()),
+            })),
+            _ => {
+                let mut keys: Vec<Filter> = Vec::new();
+
+                for value in values.iter() {
+                    let parts = value
+                        .as_array()
+                        .filter(|parts| parts.len() == columns.len())
+                        .ok_or_else(|| {
+                            AppError::new(
+                                StatusCode::BAD_REQUEST,
+                                format!(
+                                    "Expected an array of {} values for ({}), got {}.",
+                                    columns.len(),
+                                    columns.join(", "),
+                                    value
+                                ),
+                            )
+                        })?;
+
+                    let conditions = columns
+                        .iter()
+                        .zip(parts)
+                        .map(|(column, part)| {
+                            Filter::Condition(Condition {
+                                column: column.clone(),
+                                op: Operator::Eq,
+                                value: part.clone(),
+                            })
+                        })
+                        .collect();
+
+                    keys.push(Filter::And(conditions));
+                }
+
+                Ok(Filter::Or(keys))
+            }
+        }
+    }
+}
+
+impl OnConflict {
+    fn push(
+        &self,
+        q_builder: &mut QueryBuilder<'_, Postgres>,
+        schema: &TableSchema,
+        unique_keys: &[Vec<String>],
+        inserted: &[&str],
+        policy: Option<&Filter>,
+    ) -> Result<(), AppError> {
+        let target: &[String] = match self.target {
+            Some(ref target) => {
+                for name in target.iter() {
+                    schema.column(name)?;
+                }
+
+                let is_unique = unique_keys.iter().any(|key| {
+                    key.len() == target.len() && key.iter().all(|name| target.contains(name))
                 });
 
-            comma_sep.push_unseparated(")");
+                if !is_unique {
+                    return Err(AppError::new(
+                        StatusCode::BAD_REQUEST,
+                        format!(
+                            "({}) is not the primary key or a unique constraint of `{}`.",
+                            target.join(", "),
+                            schema.name.as_str()
+                        ),
+                    ));
+                }
+
+                target
+            }
+            None => unique_keys.first().ok_or_else(|| {
+                AppError::new(
+                    StatusCode::BAD_REQUEST,
+                    format!(
+                        "Table `{}` has no primary key or unique constraint to upsert on.",
+                        schema.name.as_str()
+                    ),
+                )
+            })?,
+        };
+
+        let update: Vec<&str> = match (&self.action, &self.update) {
+            (ConflictAction::Nothing, _) => Vec::new(),
+            (ConflictAction::Update, Some(update)) => {
+                for name in update.iter() {
+                    if !inserted.contains(&name.as_str()) {
+                        return Err(AppError::new(
+                            StatusCode::BAD_REQUEST,
+                            format!(
+                                "Column `{}` must be inserted to be updated on conflict.",
+                                name
+                            ),
+                        ));
+                    }
+                }
+
+                update.iter().map(String::as_str).collect()
+            }
+            (ConflictAction::Update, None) => inserted
+                .iter()
+                .copied()
+                .filter(|name| !target.iter().any(|key| key == name))
+                .collect(),
+        };
+
+        q_builder.push(" ON CONFLICT (");
+
+        let mut comma_sep = q_builder.separated(", ");
+
+        target.iter().for_each(|name| {
+            comma_sep.push(Ident::from_catalog(name));
+        });
+
+        if update.is_empty() {
+            q_builder.push(") DO NOTHING");
+
+            return Ok(());
         }
 
-        q_builder.push(" RETURNING *");
+        q_builder.push(") DO UPDATE SET ");
 
-        q_builder.build().sql().to_string()
-    }
+        for (i, name) in update.iter().enumerate() {
+            if i > 0 {
+                q_builder.push(", ");
+            }
 
-    fn push_delete(&self) -> String {
-        let mut q_builder: QueryBuilder<'_, Postgres> = QueryBuilder::new("DELETE FROM ");
+            let ident = schema.column(name)?.ident();
+
+            q_builder.push(format_args!("{} = EXCLUDED.{}", ident, ident));
+        }
 
-        q_builder.push(self.table.as_str());
+        // Conflicting rows outside the policy are left alone.
+        // Checked in a subquery since bare column names are ambiguous with EXCLUDED here.
+        if let Some(policy) = policy {
+            q_builder.push(format_args!(
+                " WHERE EXISTS (SELECT FROM {} AS existing WHERE existing.ctid = {}.ctid AND ",
+                schema.name, schema.name
+            ));
 
-        self.push_filter(&mut q_builder);
+            policy.push(q_builder, schema)?;
 
-        q_builder.build().sql().to_string()
+            q_builder.push(")");
+        }
+
+        Ok(())
+    }
+}
+
+// RETURNING {columns}, every column when none are given
+fn push_returning(
+    q_builder: &mut QueryBuilder<'_, Postgres>,
+    schema: &TableSchema,
+    returning: Option<&[String]>,
+) -> Result<(), AppError> {
+    q_builder.push(" RETURNING ");
+
+    match returning {
+        Some(columns) if !columns.is_empty() => {
+            for (i, name) in columns.iter().enumerate() {
+                if i > 0 {
+                    q_builder.push(", ");
+                }
+
+                q_builder.push(schema.column(name)?.ident());
+            }
+        }
+        _ => {
+            q_builder.push("*");
+        }
     }
 
-    fn push_filter(&self, q_builder: &mut QueryBuilder<'_, Postgres>) {
-        if let Some(ref filters) = self.filters {
-            q_builder.push(format_args!(" WHERE {} in ", filters.name));
+    Ok(())
+}
+
+impl Row {
+    fn push_insert(
+        &self,
+        schema: &TableSchema,
+        unique_keys: &[Vec<String>],
+        policy: Option<&Filter>,
+    ) -> Result<QueryBuilder<'static, Postgres>, AppError> {
+        let mut q_builder: QueryBuilder<'_, Postgres> = QueryBuilder::new("INSERT INTO ");
+
+        q_builder.push(&schema.name);
 
-            match &filters.value {
-                Value::String(s) => {
-                    q_builder.push(format_args!("'{}'", s));
+        if let Some(ref columns) = self.columns {
+            q_builder.push(" (");
+
+            for (i, col) in columns.iter().enumerate() {
+                if i > 0 {
+                    q_builder.push(", ");
                 }
-                _ => {
-                    q_builder.push(&filters.value);
+
+                q_builder.push(schema.column(&col.name)?.ident());
+            }
+
+            q_builder.push(") VALUES (");
+
+            for (i, col) in columns.iter().enumerate() {
+                if i > 0 {
+                    q_builder.push(", ");
                 }
+
+                self.push_value(&mut q_builder, schema, col)?;
             }
+
+            q_builder.push(")");
+        } else {
+            q_builder.push(" DEFAULT VALUES");
         }
+
+        if let Some(ref on_conflict) = self.on_conflict {
+            let inserted: Vec<&str> = self
+                .columns
+                .iter()
+                .flatten()
+                .map(|col| col.name.as_str())
+                .collect();
+
+            on_conflict.push(&mut q_builder, schema, unique_keys, &inserted, policy)?;
+        }
+
+        push_returning(&mut q_builder, schema, self.returning.as_deref())?;
+
+        Ok(q_builder)
+    }
+
+    fn push_value(
+        &self,
+        q_builder: &mut QueryBuilder<'_, Postgres>,
+        schema: &TableSchema,
+        col: &column::InsertOnColumn,
+    ) -> Result<(), AppError> {
+        if col.is_db_expression {
+            return utils::push_db_expression(q_builder, &col.value);
+        }
+
+        utils::push_bind_value(q_builder, &col.value, schema.column(&col.name)?);
+
+        Ok(())
     }
 }
 
 struct SelectBuilder<'a> {
     builder: QueryBuilder<'a, Postgres>,
-    table: String,
-    // Comma separated column/s
-    columns: Option<CsString>,
+    schema: TableSchema,
+    // Columns the rows are ordered by, with the primary key last as a tiebreaker
+    keys: Vec<SortKey>,
+    // Sort key columns that were selected for the cursor but not requested
+    hidden: Vec<String>,
+    has_where: bool,
     limit: Option<i64>,
-    // Comma separated column/s to order by
-    order_by: Option<CsString>,
-    // ASC or DESC
-    // ASC by default
-    order: Option<String>,
+    offset: Option<i64>,
+    cursor: Option<Cursor>,
 }
 
 impl SelectQuery {
-    fn push_select(self) -> SelectBuilder<'static> {
+    fn push_select(self, schema: TableSchema) -> Result<SelectBuilder<'static>, AppError> {
+        if self.limit.is_some_and(|limit| limit < 0) || self.offset.is_some_and(|offset| offset < 0)
+        {
+            return Err(AppError::new(
+                StatusCode::BAD_REQUEST,
+                "`limit` and `offset` must not be negative.",
+            ));
+        }
+
+        // A page without rows can't have a cursor to the next one
+        if self.limit == Some(0) {
+            return Err(AppError::new(
+                StatusCode::BAD_REQUEST,
+                "`limit` must be at least 1.",
+            ));
+        }
+
+        // One extra row is fetched to know if there is a next page
+        if self.limit.is_some_and(|limit| limit == i64::MAX) {
+            return Err(AppError::new(
+                StatusCode::BAD_REQUEST,
+                format!("`limit` must be less than {}.", i64::MAX),
+            ));
+        }
+
+        if self.cursor.is_some() && self.offset.is_some() {
+            return Err(AppError::new(
+                StatusCode::BAD_REQUEST,
+                "`cursor` and `offset` can't be used together.",
+            ));
+        }
+
+        let keys = self.sort_keys(&schema)?;
+        let cursor = self.cursor.as_deref().map(Cursor::decode).transpose()?;
+
+        if cursor.is_some() && keys.is_empty() {
+            return Err(AppError::new(
+                StatusCode::BAD_REQUEST,
+                "Cursor pagination requires an order or a primary key.",
+            ));
+        }
+
         let mut q_builder: QueryBuilder<'_, Postgres> = QueryBuilder::new("SELECT ");
+        let mut hidden: Vec<String> = Vec::new();
 
         if let Some(ref columns) = self.columns {
-            q_builder.push(columns);
+            let mut names: Vec<&str> = Vec::new();
+
+            for name in columns.split(',') {
+                names.push(schema.column(name.trim())?.name.as_str());
+            }
+
+            // The cursor is built from the last row, so it needs every sort key
+            for key in keys.iter() {
+                if !names.contains(&key.column.name.as_str()) {
+                    names.push(key.column.name.as_str());
+                    hidden.push(key.column.name.clone());
+                }
+            }
+
+            let mut comma_sep = q_builder.separated(", ");
+
+            names.iter().for_each(|name| {
+                comma_sep.push(Ident::from_catalog(name));
+            });
         } else {
             q_builder.push("*");
         };
 
-        q_builder.push(format_args!(" FROM {}", self.table));
+        q_builder.push(format_args!(" FROM {}", schema.name));
 
-        SelectBuilder {
+        Ok(SelectBuilder {
             builder: q_builder,
-            table: self.table,
-            order: self.order,
-            columns: self.columns,
-            order_by: self.order_by,
+            schema,
+            keys,
+            hidden,
+            has_where: false,
             limit: self.limit,
+            offset: self.offset,
+            cursor,
+        })
+    }
+
+    fn sort_keys(&self, schema: &TableSchema) -> Result<Vec<SortKey>, AppError> {
+        let mut keys: Vec<SortKey> = match (&self.sort, &self.order_by) {
+            (Some(_), Some(_)) => {
+                return Err(AppError::new(
+                    StatusCode::BAD_REQUEST,
+                    "`sort` and `order_by` can't be used together.",
+                ))
+            }
+            (Some(ref sort), None) => SortKey::parse_list(sort, schema)?,
+            (None, Some(ref columns)) => {
+                let order = match self.order {
+                    Some(ref order) => Order::parse(order)?,
+                    None => Order::Ascending,
+                };
+
+                let mut keys = SortKey::parse_list(columns, schema)?;
+
+                keys.iter_mut()
+                    .for_each(|key| *key = SortKey::new(key.column.clone(), order));
+
+                keys
+            }
+            (None, None) => Vec::new(),
+        };
+
+        for column in schema.primary_key() {
+            if !keys.iter().any(|key| key.column.name == column.name) {
+                keys.push(SortKey::new(column.clone(), Order::Ascending));
+            }
         }
+
+        Ok(keys)
     }
 }
 
@@ -302,10 +1198,26 @@ impl SelectBuilder<'_> {
         self.builder.sql().to_string()
     }
 
+    fn push_where(&mut self) {
+        if self.has_where {
+            self.builder.push(" AND ");
+        } else {
+            self.builder.push(" WHERE ");
+            self.has_where = true;
+        }
+    }
+
     fn order(&mut self) -> &mut Self {
-        if let (Some(ref column), Some(ref order)) = (&self.order_by, &self.order) {
-            self.builder
-                .push(format_args!(" ORDER BY {} {}", column, order));
+        if !self.keys.is_empty() {
+            self.builder.push(" ORDER BY ");
+
+            for (i, key) in self.keys.iter().enumerate() {
+                if i > 0 {
+                    self.builder.push(", ");
+                }
+
+                key.push(&mut self.builder);
+            }
         }
 
         self
@@ -313,16 +1225,125 @@ impl SelectBuilder<'_> {
 
     fn limit(&mut self) -> &mut Self {
         if let Some(ref limit) = self.limit {
-            self.builder.push(format_args!(" LIMIT {}", limit));
+            self.builder.push(format_args!(" LIMIT {}", limit + 1));
         }
 
         self
     }
 
-    // NOTE: Assumes the filter is always an `id`
-    fn filter(&mut self, filter: String) -> &mut Self {
-        self.builder.push(format_args!(" WHERE id = '{}'", filter));
+    fn offset(&mut self) -> &mut Self {
+        if let Some(ref offset) = self.offset {
+            self.builder.push(format_args!(" OFFSET {}", offset));
+        }
 
         self
     }
+
+    fn conditions(&mut self, filter: Option<&Filter>) -> Result<&mut Self, AppError> {
+        if let Some(filter) = filter {
+            self.push_where();
+
+            filter.push(&mut self.builder, &self.schema)?;
+        }
+
+        Ok(self)
+    }
+
+    // Skips every row up to and including the cursor
+    fn after(&mut self) -> Result<&mut Self, AppError> {
+        if let Some(cursor) = self.cursor.take() {
+            self.push_where();
+
+            cursor.push(&mut self.builder, &self.keys)?;
+        }
+
+        Ok(self)
+    }
+
+    fn primary_key(&mut self, id: &str) -> Result<&mut Self, AppError> {
+        self.push_where();
+
+        let columns = self.schema.primary_key();
+
+        if columns.is_empty() {
+            return Err(AppError::new(
+                StatusCode::BAD_REQUEST,
+                format!("Table `{}` has no primary key.", self.schema.name.as_str()),
+            ));
+        }
+
+        let values = split_key(id);
+
+        if values.len() != columns.len() {
+            return Err(AppError::new(
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "Expected {} primary key value(s) for ({}), got {}.",
+                    columns.len(),
+                    columns
+                        .iter()
+                        .map(|col| col.name.as_str())
+                        .collect::<Vec<&str>>()
+                        .join(", "),
+                    values.len()
+                ),
+            ));
+        }
+
+        for (i, (column, value)) in columns.into_iter().zip(values).enumerate() {
+            if i > 0 {
+                self.builder.push(" AND ");
+            }
+
+            self.builder.push(format_args!("{} = ", column.ident()));
+
+            utils::push_bind_value(&mut self.builder, &Value::String(value), column);
+        }
+
+        Ok(self)
+    }
+}
+
+// Splits a composite key on unescaped commas
+fn split_key(id: &str) -> Vec<String> {
+    let mut values: Vec<String> = vec![String::new()];
+    let mut chars = id.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                if let Some(escaped) = chars.next() {
+                    values.last_mut().unwrap().push(escaped);
+                }
+            }
+            ',' => values.push(String::new()),
+            _ => values.last_mut().unwrap().push(c),
+        }
+    }
+
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_key() {
+        assert_eq!(split_key("42"), ["42"]);
+        assert_eq!(split_key(""), [""]);
+    }
+
+    #[test]
+    fn composite_key() {
+        assert_eq!(split_key("7,en"), ["7", "en"]);
+        assert_eq!(split_key("7,"), ["7", ""]);
+    }
+
+    #[test]
+    fn escaped_commas_and_backslashes() {
+        assert_eq!(split_key("a\\,b,c"), ["a,b", "c"]);
+        assert_eq!(split_key("a\\\\,b"), ["a\\", "b"]);
+        assert_eq!(split_key("a\\"), ["a"]);
+    }
 }
@@ -2,31 +2,218 @@
 // Function for creating new columns for the new table
 
 use axum::{
-    extract::{Path, Query, State},
+    extract::State,
     http::StatusCode,
-    response::Result,
+    response::{IntoResponse, Response, Result},
 };
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
-use sqlx::{prelude::FromRow, query_builder, Execute, PgPool, Postgres, QueryBuilder, Row};
+use sqlx::{
+    prelude::FromRow, Connection, Execute, PgConnection, PgPool, Postgres, QueryBuilder,
+    Transaction,
+};
+use std::collections::HashSet;
 use tracing::{debug, info, warn};
 
-use crate::error::AppError;
+use crate::{
+    auth::{
+        permission::{Permission, Role, TablePermissions, RESERVED_TABLES},
+        AuthUser,
+    },
+    error::AppError,
+    utils::{
+        ident::Ident,
+        schema::{ConstraintSchema, TableSchema},
+    },
+};
 
-use super::column;
+use super::{
+    alter::{AlterPlan, AlterStep, SchemaChange},
+    column::{self, ColumnState},
+    extract::{Path, Query},
+    validation::{ValidJson, Validate, Validator},
+};
 
 #[derive(Debug, Deserialize)]
 pub struct Table {
     name: String,
     columns: Vec<column::BuildColumn>,
+    // Column sets of composite unique constraints, single columns use `is_unique`
+    #[serde(default)]
+    unique: Vec<Vec<String>>,
+    #[serde(default)]
+    checks: Vec<column::Check>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct EditTable {
-    name: String, // New table name
+    // Renames the table
+    name: Option<String>,
     columns: Vec<column::EditColumn>,
 }
 
+impl Validate for Table {
+    fn validate(&self, validator: &mut Validator) {
+        validator.ident("name", &self.name);
+
+        if self.columns.is_empty() {
+            validator.error("columns", "At least one column is required.");
+        }
+
+        let mut names: HashSet<&str> = HashSet::new();
+
+        for (i, col) in self.columns.iter().enumerate() {
+            validator.ident(format!("columns[{}].name", i), &col.name);
+
+            if !names.insert(&col.name) {
+                validator.error(
+                    format!("columns[{}].name", i),
+                    format!("Column `{}` is defined more than once.", col.name),
+                );
+            }
+
+            validator.data_type(format!("columns[{}].data_type", i), &col.data_type);
+
+            if col.default.is_some() && col.drop_default {
+                validator.error(
+                    format!("columns[{}].drop_default", i),
+                    "Pass either `default` or `drop_default`, not both.",
+                );
+            }
+        }
+
+        for (i, columns) in self.unique.iter().enumerate() {
+            let field = format!("unique[{}]", i);
+
+            if columns.len() < 2 {
+                validator.error(
+                    field.clone(),
+                    "List at least two columns, or use `is_unique` on the column.",
+                );
+            }
+
+            for name in columns.iter().filter(|name| !names.contains(name.as_str())) {
+                validator.error(field.clone(), format!("Column `{}` is not defined.", name));
+            }
+
+            validator.ident_list(&field, columns);
+        }
+
+        let mut check_names: HashSet<&str> = HashSet::new();
+
+        for (i, check) in self.checks.iter().enumerate() {
+            validator.ident(format!("checks[{}].name", i), &check.name);
+            validator.expression(format!("checks[{}].expression", i), &check.expression);
+
+            if !check_names.insert(&check.name) {
+                validator.error(
+                    format!("checks[{}].name", i),
+                    format!("Check `{}` is defined more than once.", check.name),
+                );
+            }
+        }
+    }
+}
+
+impl Validate for EditTable {
+    fn validate(&self, validator: &mut Validator) {
+        if let Some(ref name) = self.name {
+            validator.ident("name", name);
+        }
+
+        if self.columns.is_empty() && self.name.is_none() {
+            validator.error(
+                "columns",
+                "At least one column is required when the table isn't renamed.",
+            );
+        }
+
+        let mut names: HashSet<&str> = HashSet::new();
+        let mut new_names: HashSet<&str> = HashSet::new();
+
+        for (i, col) in self.columns.iter().enumerate() {
+            let field = format!("columns[{}]", i);
+
+            validator.ident(format!("{}.name", field), &col.name);
+
+            if !names.insert(&col.name) {
+                validator.error(
+                    format!("{}.name", field),
+                    format!("Column `{}` is listed more than once.", col.name),
+                );
+            }
+
+            if let Some(ref new_name) = col.new_name {
+                validator.ident(format!("{}.new_name", field), new_name);
+
+                if col.state != ColumnState::Modified {
+                    validator.error(
+                        format!("{}.new_name", field),
+                        "Only a modified column can be renamed.",
+                    );
+                }
+
+                if !new_names.insert(new_name) {
+                    validator.error(
+                        format!("{}.new_name", field),
+                        format!("Another column is already renamed to `{}`.", new_name),
+                    );
+                }
+            }
+
+            if col.default.is_some() && col.drop_default {
+                validator.error(
+                    format!("{}.drop_default", field),
+                    "Pass either `default` or `drop_default`, not both.",
+                );
+            }
+
+            let is_changed = matches!(col.state, ColumnState::Added | ColumnState::Modified);
+
+            if is_changed {
+                validator.data_type(format!("{}.data_type", field), &col.data_type);
+            }
+        }
+    }
+}
+
+impl EditTable {
+    // Primary key columns once the changes are applied. Added and modified columns are
+    // in it when they ask to be, removed ones never are and the rest stay as they are.
+    fn primary_key<'a>(&'a self, schema: &'a TableSchema) -> Vec<&'a str> {
+        let change = |name: &str| self.columns.iter().find(|col| col.name == name);
+
+        let current = schema
+            .primary_key()
+            .into_iter()
+            .map(|col| col.name.as_str())
+            .filter(|name| match change(name) {
+                Some(col) => match col.state {
+                    ColumnState::Added | ColumnState::Modified => col.is_primary_key,
+                    ColumnState::Removed => false,
+                    ColumnState::Unchanged => true,
+                },
+                None => true,
+            });
+
+        let added = self
+            .columns
+            .iter()
+            .filter(|col| matches!(col.state, ColumnState::Added | ColumnState::Modified))
+            .filter(|col| col.is_primary_key)
+            .map(|col| col.name.as_str());
+
+        let mut primary_key: Vec<&str> = current.collect();
+
+        for name in added {
+            if !primary_key.contains(&name) {
+                primary_key.push(name);
+            }
+        }
+
+        primary_key
+    }
+}
+
 #[derive(Debug, Serialize, FromRow)]
 pub struct TableColumnInfoPk {
     table_name: String,
@@ -48,8 +235,10 @@ pub struct TableColumnInfo {
     character_maximum_length: Option<i32>,
 }
 
+// Only lists tables the user, or the API key, can read
 pub async fn get_tables(
     State(pool): State<PgPool>,
+    user: AuthUser,
 ) -> Result<(StatusCode, axum::Json<Vec<TableColumnInfo>>), AppError> {
     let tables = sqlx::query_as::<_, TableColumnInfo>(
         r#"
@@ -61,11 +250,27 @@ pub async fn get_tables(
             is_nullable,
             character_maximum_length
         FROM
-            information_schema.columns
+            information_schema.columns AS cols
         WHERE
-            table_schema = 'public' AND table_name <> '_sqlx_migrations';
+            table_schema = 'public'
+            AND table_name <> ALL($1)
+            AND (
+                $2 OR COALESCE(
+                    (
+                        SELECT p.can_read FROM table_permissions p
+                        WHERE p.role = $3 AND p.table_name = cols.table_name
+                    ),
+                    $4
+                )
+            )
+            AND ($5::text[] IS NULL OR table_name = ANY($5));
         "#,
     )
+    .bind(RESERVED_TABLES)
+    .bind(user.role == Role::Admin)
+    .bind(user.role)
+    .bind(TablePermissions::default_for(user.role).can_read)
+    .bind(user.api_key.as_ref().and_then(|key| key.tables.as_ref()))
     .fetch_all(&pool)
     .await?;
 
@@ -74,8 +279,14 @@ pub async fn get_tables(
 
 pub async fn get_table(
     State(pool): State<PgPool>,
+    user: AuthUser,
     Path(name): Path<String>,
 ) -> Result<(StatusCode, axum::Json<Vec<TableColumnInfoPk>>), AppError> {
+    let name = Ident::parse(&name)?;
+
+    user.authorize(&pool, &name, Permission::Read).await?;
+
+    // The regclass cast folds unquoted names to lower case, so it's given the quoted one
     let table = sqlx::query_as::<_, TableColumnInfoPk>(
         r#"
         WITH PrimaryKey AS (
@@ -91,7 +302,7 @@ pub async fn get_table(
             cols.table_name,
             cols.column_name,
             cols.column_default,
-            cols.data_type,
+            format_type(a.atttypid, a.atttypmod) AS data_type,
             cols.is_nullable,
             cols.character_maximum_length,
             CASE 
@@ -100,12 +311,17 @@ pub async fn get_table(
             END AS is_primary_key
         FROM
             information_schema.columns AS cols
+        -- Types as `format_type()` spells them, e.g. `character varying(20)` or
+        -- `integer[]`, so they can be sent back in `PUT /tables/:name`
+        JOIN pg_attribute a ON a.attrelid = ($1)::regclass AND a.attname = cols.column_name
         LEFT JOIN PrimaryKey pk ON pk.attname = cols.column_name
         WHERE
-            table_name = ($1);
+            table_schema = 'public'
+            AND table_name = ($2);
         "#,
     )
-    .bind(name)
+    .bind(name.to_string())
+    .bind(name.as_str())
     .fetch_all(&pool)
     .await?;
 
@@ -114,8 +330,14 @@ pub async fn get_table(
 
 pub async fn create_table(
     State(pool): State<PgPool>,
-    axum::Json(table): axum::Json<Table>,
-) -> Result<(StatusCode, axum::Json<Vec<TableColumnInfo>>), AppError> {
+    user: AuthUser,
+    Query(query): Query<DryRunQuery>,
+    ValidJson(table): ValidJson<Table>,
+) -> Result<Response, AppError> {
+    let name = Ident::parse(&table.name)?;
+
+    user.authorize(&pool, &name, Permission::Alter).await?;
+
     let mut txn = pool.begin().await?;
 
     let exists = sqlx::query_scalar(
@@ -142,17 +364,34 @@ pub async fn create_table(
     let mut q_builder: QueryBuilder<'_, Postgres> =
         QueryBuilder::new("CREATE TABLE IF NOT EXISTS ");
 
-    q_builder.push(&table.name);
+    q_builder.push(&name);
 
-    column::BuildColumn::build_columns(&mut q_builder, &table.columns);
+    column::BuildColumn::build_columns(
+        &mut q_builder,
+        &table.columns,
+        &table.unique,
+        &table.checks,
+    )?;
 
-    let sql = q_builder.build().sql();
+    let sql = q_builder.sql().trim_end().to_string();
 
     debug!("{}", sql);
 
-    sqlx::query(sql).execute(&mut *txn).await?;
+    sqlx::query(&sql).execute(&mut *txn).await?;
+
+    // A dry run responds with the statement, otherwise with the created columns
+    if query.dry_run {
+        txn.rollback().await?;
+
+        let step = AlterStep {
+            sql,
+            destructive: false,
+        };
 
-    let table = sqlx::query_as::<_, TableColumnInfo>(
+        return Ok(axum::Json(SchemaChange::new(vec![step], false)).into_response());
+    }
+
+    let columns = sqlx::query_as::<_, TableColumnInfo>(
         r#"
         SELECT
             table_name,
@@ -164,137 +403,641 @@ pub async fn create_table(
         FROM
             information_schema.columns
         WHERE
-            table_name = ($1);
+            table_schema = 'public'
+            AND table_name = ($1)
+        ORDER BY ordinal_position;
         "#,
     )
-    .bind(table.name)
+    .bind(name.as_str())
     .fetch_all(&mut *txn)
     .await?;
 
     txn.commit().await?;
 
-    Ok((StatusCode::CREATED, axum::Json(table)))
+    Ok((StatusCode::CREATED, axum::Json(columns)).into_response())
 }
 
+// Applies every change in one transaction, renames last
 pub async fn update_table(
     State(pool): State<PgPool>,
+    user: AuthUser,
     Path(name): Path<String>,
-    axum::Json(table): axum::Json<EditTable>,
-) -> Result<StatusCode, AppError> {
+    Query(query): Query<DryRunQuery>,
+    ValidJson(table): ValidJson<EditTable>,
+) -> Result<(StatusCode, axum::Json<SchemaChange>), AppError> {
     info!("Updating table: {}", name);
 
-    let mut q_builder: QueryBuilder<'_, Postgres> =
-        QueryBuilder::new(format!("ALTER TABLE {} ", name));
+    let name = Ident::parse(&name)?;
 
-    let mut comma_sep = q_builder.separated(", ");
+    user.authorize(&pool, &name, Permission::Alter).await?;
+
+    let new_name = authorize_rename(&pool, &user, &name, table.name.as_deref()).await?;
+
+    let mut txn = pool.begin().await?;
 
-    table
-        .columns
+    let schema = TableSchema::fetch(&mut *txn, &name).await?;
+    let constraints = schema.constraints(&mut *txn).await?;
+
+    let mut plan = AlterPlan::new(name.clone());
+
+    for col in table.columns.iter() {
+        match col.state {
+            ColumnState::Removed => warn!("Removing column: {}", col.name),
+            ColumnState::Unchanged => {}
+            _ => info!("Updating column: {}", col.name),
+        }
+
+        col.plan(&mut plan, &schema, &constraints)?;
+    }
+
+    let current_primary_key: Vec<&str> = schema
+        .primary_key()
         .iter()
-        .for_each(|col| match col.state.as_str() {
-            "added" => {
-                info!("Adding column: {}", col.name);
-                comma_sep.push(format_args!(
-                    "ADD COLUMN IF NOT EXISTS {} {}",
-                    col.name.as_str(),
-                    col.data_type.as_str()
-                ));
+        .map(|col| col.name.as_str())
+        .collect();
+
+    plan.set_primary_key(
+        &constraints,
+        &current_primary_key,
+        &table.primary_key(&schema),
+    )?;
+
+    if let Some(ref new_name) = new_name {
+        plan.rename_to(new_name.clone());
+    }
+
+    plan.execute(&mut txn).await?;
+
+    // Access rules refer to tables by name
+    if let Some(new_name) = new_name {
+        rename_access_rules(&mut txn, &name, &new_name).await?;
+    }
+
+    finish(txn, query.dry_run).await?;
+
+    Ok((StatusCode::OK, axum::Json(plan.summary(!query.dry_run))))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DryRunQuery {
+    // Runs the statements and rolls back, so Postgres still checks them
+    #[serde(default)]
+    dry_run: bool,
+}
+
+async fn finish(txn: Transaction<'_, Postgres>, dry_run: bool) -> Result<(), AppError> {
+    match dry_run {
+        true => txn.rollback().await?,
+        false => txn.commit().await?,
+    }
+
+    Ok(())
+}
+
+// Changes a table to match a full definition, see `Table::diff`.
+// Renames the table when `name` differs from the path.
+pub async fn replace_table(
+    State(pool): State<PgPool>,
+    user: AuthUser,
+    Path(name): Path<String>,
+    Query(query): Query<DryRunQuery>,
+    ValidJson(table): ValidJson<Table>,
+) -> Result<(StatusCode, axum::Json<SchemaChange>), AppError> {
+    let name = Ident::parse(&name)?;
+
+    user.authorize(&pool, &name, Permission::Alter).await?;
+
+    let new_name = authorize_rename(&pool, &user, &name, Some(&table.name)).await?;
+
+    let mut txn = pool.begin().await?;
+
+    let schema = TableSchema::fetch(&mut *txn, &name).await?;
+    let constraints = schema.constraints(&mut *txn).await?;
+
+    let checks = deparse_checks(&mut txn, &table).await?;
+
+    let mut plan = table.diff(&schema, &constraints, &checks)?;
+
+    if let Some(ref new_name) = new_name {
+        plan.rename_to(new_name.clone());
+    }
+
+    info!("Replacing table: {}", name.as_str());
+
+    plan.execute(&mut txn).await?;
+
+    if let Some(new_name) = new_name {
+        rename_access_rules(&mut txn, &name, &new_name).await?;
+    }
+
+    finish(txn, query.dry_run).await?;
+
+    Ok((StatusCode::OK, axum::Json(plan.summary(!query.dry_run))))
+}
+
+impl Table {
+    // Plans the changes that turn the current table into this definition.
+    // Columns are matched by name, so one missing from the definition is dropped and
+    // a renamed column is dropped and added again.
+    fn diff(
+        &self,
+        schema: &TableSchema,
+        constraints: &[ConstraintSchema],
+        checks: &[ConstraintSchema],
+    ) -> Result<AlterPlan, AppError> {
+        let mut plan = AlterPlan::new(schema.name.clone());
+
+        for current in schema.columns.iter() {
+            if !self.columns.iter().any(|col| col.name == current.name) {
+                plan.destructive_action(format!("DROP COLUMN {}", current.ident()));
             }
-            "removed" => {
-                warn!("Removing column: {}", col.name);
-                comma_sep.push(format_args!("DROP COLUMN IF EXISTS {}", col.name.as_str()));
+        }
+
+        for col in self.columns.iter() {
+            let current = schema
+                .columns
+                .iter()
+                .find(|current| current.name == col.name);
+            let change = col.as_change(current);
+
+            match current {
+                Some(current) => change.plan_changes(&mut plan, current, constraints),
+                None => change.plan(&mut plan, schema, constraints)?,
             }
-            "modified" => {
-                // NOTE: I might need a reference to the old table in order to modify
-                info!("Updating column: {}", col.name);
+        }
 
-                // Only contains two elements, the old name and the new name
-                let names: Vec<&str> = col.name.split(',').collect();
+        let primary_key: Vec<&str> = self
+            .columns
+            .iter()
+            .filter(|col| col.is_primary_key)
+            .map(|col| col.name.as_str())
+            .collect();
 
-                if names.len() > 1 {
-                    comma_sep.push(format_args!("RENAME COLUMN {} TO {}", names[0], names[1]));
-                }
+        let current_primary_key: Vec<&str> = schema
+            .primary_key()
+            .iter()
+            .map(|col| col.name.as_str())
+            .collect();
 
-                match col.default.as_ref() {
-                    Some(Value::String(s)) => {
-                        comma_sep.push(format_args!(
-                            "ALTER COLUMN {} SET DEFAULT '{}'",
-                            names[0], s
-                        ));
-                    }
-                    Some(default) => {
-                        comma_sep.push(format_args!(
-                            "ALTER COLUMN {} SET DEFAULT {}",
-                            names[0], default
-                        ));
-                    }
-                    None => {
-                        comma_sep.push(format_args!("ALTER COLUMN {} DROP DEFAULT", names[0]));
-                    }
-                }
+        plan.set_primary_key(constraints, &current_primary_key, &primary_key)?;
+
+        // Single column unique constraints were compared with the columns
+        let composite_unique = constraints
+            .iter()
+            .filter(|constraint| constraint.kind == "u" && constraint.columns.len() > 1);
+
+        for constraint in composite_unique.clone() {
+            if !self.unique.contains(&constraint.columns) {
+                plan.drop_constraint(&constraint.name);
+            }
+        }
 
-                comma_sep.push(format_args!(
-                    "ALTER COLUMN {} SET DATA TYPE {}",
-                    names[0], col.data_type
-                ));
+        for columns in self.unique.iter() {
+            if !composite_unique
+                .clone()
+                .any(|constraint| constraint.columns == *columns)
+            {
+                let columns: Vec<&str> = columns.iter().map(String::as_str).collect();
+
+                plan.action(format!("ADD UNIQUE ({})", column::column_list(&columns)?));
             }
-            "unchanged" => {}
-            _ => {
-                warn!("Invalid column state.")
+        }
+
+        // A check whose expression changed is dropped and added again, `checks` are
+        // the requested ones as Postgres stores them
+        let current_checks = constraints
+            .iter()
+            .filter(|constraint| constraint.kind == "c");
+        let is_same_check = |current: &ConstraintSchema, requested: &ConstraintSchema| {
+            current.name == requested.name && current.definition == requested.definition
+        };
+
+        for constraint in current_checks.clone() {
+            if !checks
+                .iter()
+                .any(|requested| is_same_check(constraint, requested))
+            {
+                plan.drop_constraint(&constraint.name);
             }
-        });
+        }
+
+        for check in self.checks.iter() {
+            let is_unchanged = checks
+                .iter()
+                .filter(|requested| requested.name == check.name)
+                .any(|requested| {
+                    current_checks
+                        .clone()
+                        .any(|constraint| is_same_check(constraint, requested))
+                });
+
+            if !is_unchanged {
+                plan.action(format!("ADD {}", check.definition()?));
+            }
+        }
+
+        Ok(plan)
+    }
+}
+
+// Postgres stores checks deparsed, e.g. `price > 0` as `CHECK ((price > (0)::numeric))`.
+// The requested checks are created on a scratch table with the same columns to read
+// them back the same way, in a savepoint that is rolled back.
+async fn deparse_checks(
+    txn: &mut Transaction<'_, Postgres>,
+    table: &Table,
+) -> Result<Vec<ConstraintSchema>, AppError> {
+    if table.checks.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut definitions: Vec<String> = Vec::new();
+
+    for column in table.columns.iter() {
+        definitions.push(format!(
+            "{} {}",
+            Ident::parse(&column.name)?,
+            column.data_type
+        ));
+    }
+
+    for check in table.checks.iter() {
+        definitions.push(check.definition()?);
+    }
+
+    let mut savepoint = txn.begin().await?;
 
-    let sql = q_builder.build().sql();
+    let sql = format!(
+        "CREATE TEMPORARY TABLE \"deparse_checks\" ({})",
+        definitions.join(", ")
+    );
 
     debug!("{}", sql);
 
-    sqlx::query(sql).execute(&pool).await?;
+    sqlx::query(&sql).execute(&mut *savepoint).await?;
+
+    let checks = sqlx::query_as::<_, ConstraintSchema>(
+        r#"
+        SELECT
+            conname::text AS name,
+            contype::text AS kind,
+            ARRAY[]::text[] AS columns,
+            pg_get_constraintdef(oid) AS definition
+        FROM pg_constraint
+        WHERE conrelid = 'pg_temp.deparse_checks'::regclass AND contype = 'c';
+        "#,
+    )
+    .fetch_all(&mut *savepoint)
+    .await?;
+
+    savepoint.rollback().await?;
+
+    Ok(checks)
+}
+
+// The new name when `requested` renames the table, which needs access to both names
+async fn authorize_rename(
+    pool: &PgPool,
+    user: &AuthUser,
+    name: &Ident,
+    requested: Option<&str>,
+) -> Result<Option<Ident>, AppError> {
+    match requested {
+        Some(requested) if requested != name.as_str() => {
+            let new_name = Ident::parse(requested)?;
+
+            user.authorize(pool, &new_name, Permission::Alter).await?;
+
+            Ok(Some(new_name))
+        }
+        _ => Ok(None),
+    }
+}
+
+async fn rename_access_rules(
+    conn: &mut PgConnection,
+    from: &Ident,
+    to: &Ident,
+) -> Result<(), AppError> {
+    sqlx::query("UPDATE table_permissions SET table_name = $2 WHERE table_name = $1")
+        .bind(from.as_str())
+        .bind(to.as_str())
+        .execute(&mut *conn)
+        .await?;
+
+    sqlx::query("UPDATE row_policies SET table_name = $2 WHERE table_name = $1")
+        .bind(from.as_str())
+        .bind(to.as_str())
+        .execute(&mut *conn)
+        .await?;
+
+    sqlx::query(
+        "UPDATE api_keys SET tables = array_replace(tables, $1, $2) WHERE $1 = ANY(tables)",
+    )
+    .bind(from.as_str())
+    .bind(to.as_str())
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+// Permissions, row policies and API key scopes for a dropped table, which would
+// otherwise apply to a new table with the same name
+async fn clear_access_rules(conn: &mut PgConnection, name: &Ident) -> Result<(), AppError> {
+    sqlx::query("DELETE FROM table_permissions WHERE table_name = $1")
+        .bind(name.as_str())
+        .execute(&mut *conn)
+        .await?;
+
+    sqlx::query("DELETE FROM row_policies WHERE table_name = $1")
+        .bind(name.as_str())
+        .execute(&mut *conn)
+        .await?;
+
+    // An empty list still limits the key, to no tables at all
+    sqlx::query("UPDATE api_keys SET tables = array_remove(tables, $1) WHERE $1 = ANY(tables)")
+        .bind(name.as_str())
+        .execute(&mut *conn)
+        .await?;
 
-    Ok(StatusCode::OK)
+    Ok(())
 }
 
 pub async fn delete_table(
     State(pool): State<PgPool>,
+    user: AuthUser,
     Path(name): Path<String>,
-) -> Result<StatusCode, AppError> {
+    Query(query): Query<DryRunQuery>,
+) -> Result<Response, AppError> {
     warn!("Deleting table: {}", name);
 
+    let name = Ident::parse(&name)?;
+
+    user.authorize(&pool, &name, Permission::Alter).await?;
+
     // NOTE: .bind() doesn't work?
     let sql = format!("DROP TABLE IF EXISTS {}", name);
 
-    sqlx::query(sql.as_str()).execute(&pool).await?;
+    debug!("{sql}");
+
+    let mut txn = pool.begin().await?;
+
+    sqlx::query(sql.as_str()).execute(&mut *txn).await?;
+
+    clear_access_rules(&mut txn, &name).await?;
 
-    Ok(StatusCode::NO_CONTENT)
+    finish(txn, query.dry_run).await?;
+
+    // Only a dry run responds with the statement
+    if !query.dry_run {
+        return Ok(StatusCode::NO_CONTENT.into_response());
+    }
+
+    let step = AlterStep {
+        sql,
+        destructive: true,
+    };
+
+    Ok(axum::Json(SchemaChange::new(vec![step], false)).into_response())
 }
 
 #[derive(Debug, Deserialize)]
 pub struct DeleteTableQuery {
     names: String, // Comma separated table names
+    #[serde(default)]
+    dry_run: bool,
 }
 
 // NOTE: Cascades when dropping tables
 pub async fn delete_tables(
     State(pool): State<PgPool>,
+    user: AuthUser,
     Query(query): Query<DeleteTableQuery>,
-) -> Result<StatusCode, AppError> {
+) -> Result<Response, AppError> {
+    let names = Ident::parse_list(&query.names)?;
+
+    for name in names.iter() {
+        user.authorize(&pool, name, Permission::Alter).await?;
+    }
+
     let mut q_builder: QueryBuilder<'_, Postgres> = QueryBuilder::new("DROP TABLE IF EXISTS ");
-    // let mut comma_sep = q_builder.separated(", ");
-    //
-    // tables.iter().for_each(|table| {
-    //     warn!("Deleting table: {}", table.name);
-    //     comma_sep.push(table.name.as_str());
-    // });
+    let mut comma_sep = q_builder.separated(", ");
 
-    // comma_sep.push_unseparated(" CASCADE");
+    names.iter().for_each(|name| {
+        warn!("Deleting table: {}", name.as_str());
+        comma_sep.push(name);
+    });
 
-    q_builder.push(query.names);
-    q_builder.push(" CASCADE");
+    comma_sep.push_unseparated(" CASCADE");
 
-    let sql = q_builder.build().sql();
+    let sql = q_builder.build().sql().to_string();
 
     debug!("{sql}");
 
-    sqlx::query(sql).execute(&pool).await?;
+    let mut txn = pool.begin().await?;
+
+    sqlx::query(&sql).execute(&mut *txn).await?;
+
+    for name in names.iter() {
+        clear_access_rules(&mut txn, name).await?;
+    }
+
+    finish(txn, query.dry_run).await?;
+
+    if !query.dry_run {
+        return Ok(StatusCode::NO_CONTENT.into_response());
+    }
+
+    let step = AlterStep {
+        sql,
+        destructive: true,
+    };
+
+    Ok(axum::Json(SchemaChange::new(vec![step], false)).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::utils::schema::ColumnSchema;
+
+    fn column(name: &str, data_type: &str, primary_key_position: Option<i32>) -> ColumnSchema {
+        ColumnSchema {
+            name: name.to_string(),
+            data_type: data_type.to_string(),
+            base_type: data_type.to_string(),
+            udt_name: data_type.to_string(),
+            is_nullable: primary_key_position.is_none(),
+            default: None,
+            is_primary_key: primary_key_position.is_some(),
+            primary_key_position,
+        }
+    }
+
+    fn constraint(name: &str, kind: &str, columns: &[&str], definition: &str) -> ConstraintSchema {
+        ConstraintSchema {
+            name: name.to_string(),
+            kind: kind.to_string(),
+            columns: columns.iter().map(|column| column.to_string()).collect(),
+            definition: definition.to_string(),
+        }
+    }
+
+    // order_lines (order_id, line, sku, price) keyed by (order_id, line)
+    fn schema() -> (TableSchema, Vec<ConstraintSchema>) {
+        let schema = TableSchema {
+            name: Ident::from_catalog("order_lines"),
+            columns: vec![
+                column("order_id", "integer", Some(1)),
+                column("line", "integer", Some(2)),
+                column("sku", "text", None),
+                column("price", "numeric", None),
+            ],
+        };
+        let constraints = vec![
+            constraint(
+                "order_lines_pkey",
+                "p",
+                &["order_id", "line"],
+                "PRIMARY KEY (order_id, line)",
+            ),
+            constraint(
+                "order_lines_order_id_sku_key",
+                "u",
+                &["order_id", "sku"],
+                "UNIQUE (order_id, sku)",
+            ),
+            positive_price("CHECK ((price > (0)::numeric))"),
+        ];
+
+        (schema, constraints)
+    }
+
+    fn positive_price(definition: &str) -> ConstraintSchema {
+        constraint("positive_price", "c", &["price"], definition)
+    }
+
+    fn table(primary_key: &[&str], unique: &[&str], check: &str) -> Table {
+        let columns: Vec<_> = ["line", "order_id", "sku", "price"]
+            .iter()
+            .map(|name| {
+                let data_type = match *name {
+                    "sku" => "text",
+                    "price" => "numeric",
+                    _ => "int",
+                };
+
+                json!({
+                    "name": name,
+                    "data_type": data_type,
+                    "is_nullable": !matches!(*name, "order_id" | "line"),
+                    "is_primary_key": primary_key.contains(name),
+                    "is_unique": false,
+                })
+            })
+            .collect();
+
+        serde_json::from_value(json!({
+            "name": "order_lines",
+            "columns": columns,
+            "unique": [unique],
+            "checks": [{ "name": "positive_price", "expression": check }],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn unchanged_constraints() {
+        let (schema, constraints) = schema();
+        let table = table(&["line", "order_id"], &["order_id", "sku"], "price > 0");
+        let checks = [positive_price("CHECK ((price > (0)::numeric))")];
+
+        let plan = table.diff(&schema, &constraints, &checks).unwrap();
+
+        assert!(plan.statements().is_empty());
+    }
+
+    #[test]
+    fn changed_constraints() {
+        let (schema, constraints) = schema();
+        let table = table(&["order_id"], &["sku", "price"], "price >= 0");
+        let checks = [positive_price("CHECK ((price >= (0)::numeric))")];
+
+        let plan = table.diff(&schema, &constraints, &checks).unwrap();
+
+        assert_eq!(
+            plan.statements(),
+            [
+                "ALTER TABLE \"order_lines\" DROP CONSTRAINT \"order_lines_pkey\", \
+                 DROP CONSTRAINT \"order_lines_order_id_sku_key\", \
+                 DROP CONSTRAINT \"positive_price\", ADD PRIMARY KEY (\"order_id\"), \
+                 ADD UNIQUE (\"sku\", \"price\"), \
+                 ADD CONSTRAINT \"positive_price\" CHECK (price >= 0)"
+            ]
+        );
+    }
 
-    Ok(StatusCode::NO_CONTENT)
+    fn edit_table(columns: &[(&str, bool, &str)]) -> EditTable {
+        let columns: Vec<_> = columns
+            .iter()
+            .map(|(name, is_primary_key, state)| {
+                json!({
+                    "name": name,
+                    "data_type": "integer",
+                    "is_nullable": false,
+                    "is_primary_key": is_primary_key,
+                    "is_unique": false,
+                    "state": state,
+                })
+            })
+            .collect();
+
+        serde_json::from_value(json!({ "columns": columns })).unwrap()
+    }
+
+    #[test]
+    fn edited_primary_key() {
+        let (schema, _) = schema();
+
+        let table = edit_table(&[("line", false, "modified"), ("sku", true, "modified")]);
+        assert_eq!(table.primary_key(&schema), ["order_id", "sku"]);
+
+        let table = edit_table(&[("order_id", false, "removed"), ("line", true, "unchanged")]);
+        assert_eq!(table.primary_key(&schema), ["line"]);
+
+        let table = edit_table(&[("id", true, "added"), ("order_id", true, "modified")]);
+        assert_eq!(table.primary_key(&schema), ["order_id", "line", "id"]);
+    }
+
+    #[test]
+    fn primary_key_changes() {
+        let (schema, constraints) = schema();
+        let current = ["order_id", "line"];
+
+        let mut plan = AlterPlan::new(schema.name.clone());
+        plan.set_primary_key(&constraints, &current, &["line", "order_id"])
+            .unwrap();
+        assert!(plan.statements().is_empty());
+
+        let mut plan = AlterPlan::new(schema.name.clone());
+        plan.set_primary_key(&constraints, &current, &["order_id", "sku"])
+            .unwrap();
+        assert_eq!(
+            plan.statements(),
+            [
+                "ALTER TABLE \"order_lines\" DROP CONSTRAINT \"order_lines_pkey\", \
+                 ADD PRIMARY KEY (\"order_id\", \"sku\")"
+            ]
+        );
+
+        let mut plan = AlterPlan::new(schema.name.clone());
+        plan.set_primary_key(&constraints, &current, &[]).unwrap();
+        assert_eq!(
+            plan.statements(),
+            ["ALTER TABLE \"order_lines\" DROP CONSTRAINT \"order_lines_pkey\""]
+        );
+    }
 }
@@ -1,19 +1,360 @@
+use std::sync::Arc;
+
 use axum::{extract::State, http::StatusCode, response::Result};
-use sqlx::PgPool;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::FromRow, PgConnection, PgPool};
+use uuid::Uuid;
+
+use crate::{
+    auth::{
+        one_time::{self, Purpose},
+        password,
+        permission::Role,
+        AuthUser,
+    },
+    error::AppError,
+    mail::Outbox,
+};
+
+use super::extract::{Json, Path};
+
+const MIN_PASSWORD_LEN: usize = 8;
+
+// Every column of `users` except the password hash
+pub const USER_COLUMNS: &str =
+    "id, created_at, email, email_verified_at, first_name, last_name, role, is_active";
+
+// A user as sent back to clients, the password hash is never selected
+#[derive(Debug, Serialize, FromRow)]
+pub struct User {
+    pub id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub email: String,
+    // Null until the user follows the link sent to their email
+    pub email_verified_at: Option<DateTime<Utc>>,
+    pub first_name: String,
+    pub last_name: String,
+    pub role: Role,
+    pub is_active: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewUser {
+    email: String,
+    password: String,
+    first_name: String,
+    last_name: String,
+    #[serde(default = "default_role")]
+    role: Role,
+}
+
+// Only the given fields are changed
+#[derive(Debug, Deserialize)]
+pub struct EditUser {
+    email: Option<String>,
+    first_name: Option<String>,
+    last_name: Option<String>,
+    // Admin only
+    role: Option<Role>,
+    // Admin only
+    is_active: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChangePassword {
+    // Required when changing your own password
+    current_password: Option<String>,
+    new_password: String,
+}
+
+fn default_role() -> Role {
+    Role::Viewer
+}
+
+// Emails are compared case insensitively, so they're stored lowercased
+pub fn normalize_email(email: &str) -> Result<String, AppError> {
+    let email = email.trim().to_lowercase();
+
+    match email.split_once('@') {
+        Some((local, domain)) if !local.is_empty() && !domain.is_empty() => Ok(email),
+        _ => Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            format!("Invalid email: `{}`", email),
+        )),
+    }
+}
+
+// After a password change, tokens issued before it stop working, see `AuthUser`, and the
+// user's API keys are revoked
+pub async fn revoke_credentials(conn: &mut PgConnection, user_id: Uuid) -> Result<(), AppError> {
+    sqlx::query("UPDATE users SET password_changed_at = now() WHERE id = $1")
+        .bind(user_id)
+        .execute(&mut *conn)
+        .await?;
+
+    sqlx::query("UPDATE api_keys SET revoked_at = now() WHERE user_id = $1 AND revoked_at IS NULL")
+        .bind(user_id)
+        .execute(&mut *conn)
+        .await?;
+
+    Ok(())
+}
+
+pub fn validate_password(password: &str) -> Result<(), AppError> {
+    if password.chars().count() < MIN_PASSWORD_LEN {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            format!(
+                "Password must be at least {} characters long.",
+                MIN_PASSWORD_LEN
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+pub fn validate_name(field: &str, name: &str) -> Result<(), AppError> {
+    if name.trim().is_empty() {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            format!("`{}` can't be empty.", field),
+        ));
+    }
+
+    Ok(())
+}
+
+pub async fn send_email_verification(
+    pool: &PgPool,
+    outbox: &Outbox,
+    user: &User,
+) -> Result<(), AppError> {
+    let token = one_time::issue(pool, user.id, Purpose::VerifyEmail).await?;
+
+    outbox.send_email_verification(&user.email, &token);
+
+    Ok(())
+}
+
+fn not_found(id: Uuid) -> AppError {
+    AppError::new(StatusCode::NOT_FOUND, format!("User `{}` not found.", id))
+}
+
+pub async fn get_users(
+    State(pool): State<PgPool>,
+    user: AuthUser,
+) -> Result<axum::Json<Vec<User>>, AppError> {
+    user.require_admin()?;
+
+    let users = sqlx::query_as::<_, User>(&format!(
+        "SELECT {} FROM users ORDER BY created_at",
+        USER_COLUMNS
+    ))
+    .fetch_all(&pool)
+    .await?;
+
+    Ok(axum::Json(users))
+}
+
+// Admins can fetch anyone, everyone else only themselves
+pub async fn get_user(
+    State(pool): State<PgPool>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> Result<axum::Json<User>, AppError> {
+    user.require_self_or_admin(id)?;
+
+    let user =
+        sqlx::query_as::<_, User>(&format!("SELECT {} FROM users WHERE id = $1", USER_COLUMNS))
+            .bind(id)
+            .fetch_optional(&pool)
+            .await?
+            .ok_or_else(|| not_found(id))?;
 
-pub async fn create_user(State(pool): State<PgPool>) -> Result<StatusCode> {
-    sqlx::query(
+    Ok(axum::Json(user))
+}
+
+// Lets admins add users with any role, unlike `/auth/register`
+pub async fn create_user(
+    State(pool): State<PgPool>,
+    State(outbox): State<Arc<Outbox>>,
+    user: AuthUser,
+    Json(body): Json<NewUser>,
+) -> Result<(StatusCode, axum::Json<User>), AppError> {
+    user.require_admin()?;
+
+    let email = normalize_email(&body.email)?;
+
+    validate_password(&body.password)?;
+    validate_name("first_name", &body.first_name)?;
+    validate_name("last_name", &body.last_name)?;
+
+    let hash = password::hash(body.password).await?;
+
+    let user = sqlx::query_as::<_, User>(&format!(
+        r#"
+        INSERT INTO users (email, password, first_name, last_name, role)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING {}
+        "#,
+        USER_COLUMNS
+    ))
+    .bind(email)
+    .bind(hash)
+    .bind(body.first_name.trim())
+    .bind(body.last_name.trim())
+    .bind(body.role)
+    .fetch_one(&pool)
+    .await?;
+
+    send_email_verification(&pool, &outbox, &user).await?;
+
+    Ok((StatusCode::CREATED, axum::Json(user)))
+}
+
+// Changing the email marks it as unverified again
+pub async fn update_user(
+    State(pool): State<PgPool>,
+    State(outbox): State<Arc<Outbox>>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+    Json(body): Json<EditUser>,
+) -> Result<axum::Json<User>, AppError> {
+    user.require_self_or_admin(id)?;
+
+    if body.role.is_some() || body.is_active.is_some() {
+        user.require_admin()?;
+    }
+
+    // Otherwise the last admin could lock everyone out
+    if id == user.id
+        && (body.role.is_some_and(|role| role != Role::Admin) || body.is_active == Some(false))
+    {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            "Admins can't demote or deactivate themselves.",
+        ));
+    }
+
+    let email = body.email.as_deref().map(normalize_email).transpose()?;
+
+    if let Some(ref first_name) = body.first_name {
+        validate_name("first_name", first_name)?;
+    }
+
+    if let Some(ref last_name) = body.last_name {
+        validate_name("last_name", last_name)?;
+    }
+
+    let user = sqlx::query_as::<_, User>(&format!(
         r#"
-        INSERT INTO users (email, password, first_name, last_name) VALUES ($1, $2, $3, $4)
+        UPDATE users SET
+            email = COALESCE($1, email),
+            email_verified_at = CASE
+                WHEN $1 IS NULL OR $1 = email THEN email_verified_at
+            END,
+            first_name = COALESCE($2, first_name),
+            last_name = COALESCE($3, last_name),
+            role = COALESCE($4, role),
+            is_active = COALESCE($5, is_active)
+        WHERE id = $6
+        RETURNING {}
         "#,
-    )
-    .bind("email@gmail.com")
-    .bind("password")
-    .bind("First")
-    .bind("Last")
-    .execute(&pool)
-    .await
-    .unwrap();
-
-    Ok(StatusCode::CREATED)
+        USER_COLUMNS
+    ))
+    .bind(email)
+    .bind(body.first_name.as_deref().map(str::trim))
+    .bind(body.last_name.as_deref().map(str::trim))
+    .bind(body.role)
+    .bind(body.is_active)
+    .bind(id)
+    .fetch_optional(&pool)
+    .await?
+    .ok_or_else(|| not_found(id))?;
+
+    if body.email.is_some() && user.email_verified_at.is_none() {
+        send_email_verification(&pool, &outbox, &user).await?;
+    }
+
+    Ok(axum::Json(user))
+}
+
+// Users change their own password with the current one, admins can reset anyone else's
+pub async fn change_password(
+    State(pool): State<PgPool>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+    Json(body): Json<ChangePassword>,
+) -> Result<StatusCode, AppError> {
+    user.require_self_or_admin(id)?;
+
+    validate_password(&body.new_password)?;
+
+    if id == user.id {
+        let hash: String = sqlx::query_scalar("SELECT password FROM users WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&pool)
+            .await?
+            .ok_or_else(|| not_found(id))?;
+
+        let current_password = body.current_password.ok_or_else(|| {
+            AppError::new(StatusCode::BAD_REQUEST, "`current_password` is required.")
+        })?;
+
+        if !password::verify(current_password, hash).await? {
+            return Err(AppError::new(
+                StatusCode::FORBIDDEN,
+                "Current password is incorrect.",
+            ));
+        }
+    }
+
+    let hash = password::hash(body.new_password).await?;
+
+    let mut txn = pool.begin().await?;
+
+    let result = sqlx::query("UPDATE users SET password = $1 WHERE id = $2")
+        .bind(hash)
+        .bind(id)
+        .execute(&mut *txn)
+        .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(not_found(id));
+    }
+
+    revoke_credentials(&mut txn, id).await?;
+
+    txn.commit().await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// Also deletes the user's API keys
+pub async fn delete_user(
+    State(pool): State<PgPool>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, AppError> {
+    user.require_admin()?;
+
+    if id == user.id {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            "Admins can't delete themselves.",
+        ));
+    }
+
+    let result = sqlx::query("DELETE FROM users WHERE id = $1")
+        .bind(id)
+        .execute(&pool)
+        .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(not_found(id));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
 }
@@ -0,0 +1,320 @@
+use std::collections::HashSet;
+
+use axum::{
+    async_trait,
+    extract::{FromRequest, Request},
+};
+use serde::de::DeserializeOwned;
+
+use crate::{
+    error::{AppError, FieldError},
+    utils::ident::Ident,
+};
+
+// Checks a payload before any SQL is built from it
+pub trait Validate {
+    fn validate(&self, validator: &mut Validator);
+}
+
+// Collects every field error so they can be reported together
+#[derive(Debug, Default)]
+pub struct Validator {
+    errors: Vec<FieldError>,
+}
+
+impl Validator {
+    pub fn error(&mut self, field: impl Into<String>, message: impl Into<String>) {
+        self.errors.push(FieldError {
+            field: field.into(),
+            message: message.into(),
+        });
+    }
+
+    // Same rules as `Ident::parse`
+    pub fn ident(&mut self, field: impl Into<String>, name: &str) {
+        if Ident::parse(name).is_err() {
+            self.error(
+                field,
+                format!(
+                    "`{}` is not a valid name, use letters, digits and underscores.",
+                    name
+                ),
+            );
+        }
+    }
+
+    // Checks each name and reports the ones given more than once.
+    // `field` is the path of the list, e.g. `returning`.
+    pub fn ident_list<'a>(&mut self, field: &str, names: impl IntoIterator<Item = &'a String>) {
+        let mut seen: HashSet<&str> = HashSet::new();
+
+        for (i, name) in names.into_iter().enumerate() {
+            let path = format!("{}[{}]", field, i);
+
+            self.ident(path.clone(), name);
+
+            if !seen.insert(name) {
+                self.error(path, format!("`{}` is listed more than once.", name));
+            }
+        }
+    }
+
+    // For SQL expressions pushed as-is inside parentheses, e.g. `CHECK (...)`.
+    // Postgres checks the expression itself, this only makes sure it can't close the
+    // parentheses or start another statement.
+    pub fn expression(&mut self, field: impl Into<String>, expression: &str) {
+        if expression.trim().is_empty() {
+            self.error(field, "An expression is required.");
+        } else if !is_enclosed(expression) {
+            self.error(
+                field,
+                "Unbalanced parentheses or quotes, or a disallowed `;`, `$`, `\\` or comment.",
+            );
+        }
+    }
+
+    // Type names are pushed as-is, so nothing but a type may follow, e.g. `DEFAULT` or `,`
+    pub fn data_type(&mut self, field: impl Into<String>, data_type: &str) {
+        if data_type.trim().is_empty() {
+            self.error(field, "A type is required.");
+        } else if !is_type_name(data_type) {
+            self.error(
+                field,
+                format!(
+                    "`{}` is not a valid type, e.g. `text`, `varchar(255)`, `numeric(10, 2)` or `integer[]`.",
+                    data_type
+                ),
+            );
+        }
+    }
+
+    pub fn finish(self) -> Result<(), AppError> {
+        match self.errors.is_empty() {
+            true => Ok(()),
+            false => Err(AppError::validation(self.errors)),
+        }
+    }
+}
+
+// Parentheses balance outside of quoted strings and names, which are closed.
+// Backslashes are refused even in strings since `E'\''` would end the string elsewhere.
+fn is_enclosed(expression: &str) -> bool {
+    if expression.contains('\\') {
+        return false;
+    }
+
+    let mut chars = expression.chars().peekable();
+    let mut depth = 0;
+
+    while let Some(c) = chars.next() {
+        match c {
+            // A doubled quote is an escaped one
+            '\'' | '"' => loop {
+                match chars.next() {
+                    Some(next) if next == c && chars.peek() == Some(&c) => {
+                        chars.next();
+                    }
+                    Some(next) if next == c => break,
+                    Some(_) => {}
+                    None => return false,
+                }
+            },
+            '(' => depth += 1,
+            ')' if depth == 0 => return false,
+            ')' => depth -= 1,
+            // Statement separators and dollar quoting
+            ';' | '$' => return false,
+            '-' if chars.peek() == Some(&'-') => return false,
+            '/' if chars.peek() == Some(&'*') => return false,
+            _ => {}
+        }
+    }
+
+    depth == 0
+}
+
+// Words of multi-word type names after the first one, e.g. `double precision`,
+// `timestamp(3) with time zone` or `interval day to second`
+const TYPE_NAME_WORDS: &[&str] = &[
+    "varying",
+    "precision",
+    "with",
+    "without",
+    "time",
+    "zone",
+    "year",
+    "month",
+    "day",
+    "hour",
+    "minute",
+    "second",
+    "to",
+];
+
+// A type name with at most one list of numeric modifiers and any number of array
+// brackets, e.g. `varchar(255)`, `numeric(10, 2)`, `timestamp(3) with time zone` or
+// `int[][]`
+fn is_type_name(data_type: &str) -> bool {
+    let mut rest = data_type.trim();
+
+    while let Some(inner) = rest.strip_suffix(']') {
+        let Some(i) = inner.rfind('[') else {
+            return false;
+        };
+
+        if !inner[i + 1..].chars().all(|c| c.is_ascii_digit()) {
+            return false;
+        }
+
+        rest = inner[..i].trim_end();
+    }
+
+    let (name, modifiers, trailing) = match rest.split_once('(') {
+        Some((name, rest)) => match rest.split_once(')') {
+            Some((modifiers, trailing)) => (name, Some(modifiers), trailing),
+            None => return false,
+        },
+        None => (rest, None, ""),
+    };
+
+    let is_modifier = |modifier: &str| {
+        let modifier = modifier.trim();
+
+        !modifier.is_empty() && modifier.chars().all(|c| c.is_ascii_digit())
+    };
+
+    let is_type_word = |word: &str| TYPE_NAME_WORDS.contains(&word.to_lowercase().as_str());
+
+    let mut words = name.split_whitespace();
+
+    words.next().is_some_and(|word| Ident::parse(word).is_ok())
+        && words.all(is_type_word)
+        && modifiers.is_none_or(|modifiers| modifiers.split(',').all(is_modifier))
+        && trailing.split_whitespace().all(is_type_word)
+}
+
+// `axum::Json` that also runs `Validate`, rejecting with an `AppError`
+pub struct ValidJson<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for ValidJson<T>
+where
+    T: DeserializeOwned + Validate,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let axum::Json(payload) = axum::Json::<T>::from_request(req, state).await?;
+
+        let mut validator = Validator::default();
+
+        payload.validate(&mut validator);
+        validator.finish()?;
+
+        Ok(Self(payload))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn enclosed_expressions() {
+        assert!(is_enclosed("price > 0"));
+        assert!(is_enclosed("(a > 0) AND (b < 10)"));
+        assert!(is_enclosed("status IN ('new', 'paid')"));
+        assert!(is_enclosed("\"Title\" <> ''"));
+    }
+
+    #[test]
+    fn quotes_hide_special_characters() {
+        assert!(is_enclosed("note <> ')'"));
+        assert!(is_enclosed("note <> ';'"));
+        assert!(is_enclosed("note <> '--'"));
+        assert!(is_enclosed("\"a;b\" > 0"));
+    }
+
+    #[test]
+    fn doubled_quotes_stay_in_the_string() {
+        assert!(is_enclosed("name <> 'it''s'"));
+        assert!(is_enclosed("\"a\"\"b\" > 0"));
+        assert!(!is_enclosed("name <> 'it''s) OR (true"));
+    }
+
+    #[test]
+    fn unclosed_quotes() {
+        assert!(!is_enclosed("name <> 'open"));
+        assert!(!is_enclosed("\"open > 0"));
+        assert!(!is_enclosed("name <> 'it''"));
+    }
+
+    #[test]
+    fn unbalanced_parentheses() {
+        assert!(!is_enclosed("price > 0) OR (true"));
+        assert!(!is_enclosed("(price > 0"));
+        assert!(!is_enclosed(")("));
+    }
+
+    #[test]
+    fn comments() {
+        assert!(!is_enclosed("price > 0 -- )"));
+        assert!(!is_enclosed("price > 0 /* ) */"));
+        assert!(is_enclosed("price > -1"));
+        assert!(is_enclosed("price / 2 > 1"));
+    }
+
+    #[test]
+    fn statement_separators_and_dollar_quotes() {
+        assert!(!is_enclosed("true); DROP TABLE posts; --"));
+        assert!(!is_enclosed("name <> $$)$$"));
+        assert!(!is_enclosed("name <> $tag$)$tag$"));
+    }
+
+    #[test]
+    fn backslashes() {
+        assert!(!is_enclosed("name <> E'\\''"));
+        assert!(!is_enclosed("name <> '\\'"));
+    }
+
+    #[test]
+    fn type_names() {
+        for data_type in [
+            "text",
+            "VARCHAR(255)",
+            "numeric(10, 2)",
+            "numeric (10,2)",
+            "integer[]",
+            "int[3][3]",
+            "double precision",
+            "character varying(20)[]",
+            "timestamp(3) with time zone",
+            "interval day to second",
+            "user_role",
+        ] {
+            assert!(is_type_name(data_type), "{}", data_type);
+        }
+    }
+
+    #[test]
+    fn not_type_names() {
+        for data_type in [
+            "",
+            "text) INHERITS (users",
+            "int, DROP COLUMN \"id\"",
+            "int REFERENCES users",
+            "int DEFAULT 1",
+            "text[",
+            "text[a]",
+            "numeric(10,",
+            "numeric(a)",
+            "numeric()",
+            "numeric(1)(2)",
+            "1int",
+            "public.user_role",
+        ] {
+            assert!(!is_type_name(data_type), "{}", data_type);
+        }
+    }
+}
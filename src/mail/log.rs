@@ -0,0 +1,61 @@
+use std::path::PathBuf;
+
+use axum::async_trait;
+use chrono::Utc;
+use tracing::info;
+
+use crate::error::AppError;
+
+use super::{Email, Mailer};
+
+// For local development and tests, emails are logged instead of sent.
+// With a directory set, each email is also written to a file in it.
+pub struct LogMailer {
+    dir: Option<PathBuf>,
+}
+
+impl LogMailer {
+    pub fn new(dir: Option<PathBuf>) -> Self {
+        Self { dir }
+    }
+}
+
+#[async_trait]
+impl Mailer for LogMailer {
+    async fn send(&self, email: Email) -> Result<(), AppError> {
+        info!(
+            "{:<12} - to: {}, subject: {}\n{}",
+            "EMAIL", email.to, email.subject, email.body
+        );
+
+        if let Some(ref dir) = self.dir {
+            // The address is user input, keep it from escaping the directory
+            let to: String = email
+                .to
+                .chars()
+                .map(|c| match c {
+                    'a'..='z' | 'A'..='Z' | '0'..='9' | '@' | '.' | '-' | '_' | '+' => c,
+                    _ => '_',
+                })
+                .collect();
+            let path = dir.join(format!(
+                "{}-{}.txt",
+                Utc::now().format("%Y%m%dT%H%M%S%.6f"),
+                to
+            ));
+            let contents = format!(
+                "To: {}\nSubject: {}\n\n{}\n",
+                email.to, email.subject, email.body
+            );
+
+            tokio::fs::create_dir_all(dir)
+                .await
+                .map_err(anyhow::Error::from)?;
+            tokio::fs::write(path, contents)
+                .await
+                .map_err(anyhow::Error::from)?;
+        }
+
+        Ok(())
+    }
+}
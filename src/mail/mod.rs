@@ -0,0 +1,86 @@
+use std::sync::Arc;
+
+use axum::async_trait;
+use tracing::warn;
+
+use crate::error::AppError;
+
+pub mod log;
+pub mod smtp;
+
+#[derive(Debug, Clone)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    // Plain text
+    pub body: String,
+}
+
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, email: Email) -> Result<(), AppError>;
+}
+
+// Writes the emails the CMS sends and hands them to the configured mailer
+pub struct Outbox {
+    mailer: Arc<dyn Mailer>,
+    // Base URL of the frontend, links in emails point to it
+    app_url: Option<String>,
+}
+
+impl Outbox {
+    pub fn new(mailer: Arc<dyn Mailer>, app_url: Option<String>) -> Self {
+        Self {
+            mailer,
+            app_url: app_url.map(|url| url.trim_end_matches('/').to_string()),
+        }
+    }
+
+    pub fn send_email_verification(&self, to: &str, token: &str) {
+        let body = format!(
+            "Confirm your email address with this token, it expires in 24 hours:\n\n{}",
+            self.link_or_token("verify-email", token)
+        );
+
+        self.send_in_background(Email {
+            to: to.to_string(),
+            subject: "Verify your email".to_string(),
+            body,
+        });
+    }
+
+    pub fn send_password_reset(&self, to: &str, token: &str) {
+        let body = format!(
+            "Someone asked to reset the password for this account. \
+             If it wasn't you, ignore this email.\n\n\
+             Reset it with this token, it expires in 1 hour:\n\n{}",
+            self.link_or_token("reset-password", token)
+        );
+
+        self.send_in_background(Email {
+            to: to.to_string(),
+            subject: "Reset your password".to_string(),
+            body,
+        });
+    }
+
+    fn link_or_token(&self, path: &str, token: &str) -> String {
+        match self.app_url {
+            Some(ref app_url) => format!("{}/{}?token={}", app_url, path, token),
+            None => token.to_string(),
+        }
+    }
+
+    // Requests don't wait on the mail server, failures are only logged
+    fn send_in_background(&self, email: Email) {
+        let mailer = self.mailer.clone();
+
+        tokio::spawn(async move {
+            let to = email.to.clone();
+
+            if let Err(err) = mailer.send(email).await {
+                warn!("Failed to send email to {}: {:?}", to, err);
+            }
+        });
+    }
+}
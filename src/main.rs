@@ -2,24 +2,32 @@
 // #![allow(unused_imports)]
 // #![allow(warnings)]
 
+use anyhow::Context;
 use axum::{
-    http,
+    http, middleware,
     response::IntoResponse,
-    routing::{get, post},
+    routing::{delete, get, post, put},
     Router,
 };
 use dotenv::dotenv;
-use std::env;
+use std::{env, sync::Arc};
 use tokio::net::TcpListener;
 use tower_http::cors::CorsLayer;
 use tracing::info;
 use tracing_subscriber::EnvFilter;
 
+mod auth;
 mod error;
 mod handlers;
+mod mail;
+mod request_id;
+mod state;
 mod utils;
 
-use handlers::{row, table, user};
+use auth::{token::Keys, AuthUser};
+use handlers::{api_key, permission, policy, row, table, user};
+use mail::{log::LogMailer, smtp::SmtpMailer, Mailer, Outbox};
+use state::{AppState, Registration};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<(), anyhow::Error> {
@@ -35,6 +43,22 @@ async fn main() -> anyhow::Result<(), anyhow::Error> {
     let max_connections = env::var("MAX_CONNECTIONS")
         .unwrap_or("10".to_string())
         .parse::<u32>()?;
+    let jwt_secret = env::var("JWT_SECRET").context("JWT_SECRET must be set")?;
+    let jwt_ttl = env::var("JWT_TTL_SECONDS")
+        .unwrap_or("86400".to_string())
+        .parse::<i64>()?;
+    let allow_registration = env::var("ALLOW_REGISTRATION")
+        .unwrap_or("false".to_string())
+        .parse::<bool>()?;
+
+    // Emails are only logged unless an SMTP server is configured
+    let mailer: Arc<dyn Mailer> = match env::var("SMTP_URL") {
+        Ok(url) => Arc::new(SmtpMailer::new(
+            &url,
+            &env::var("MAIL_FROM").unwrap_or("CMS <no-reply@localhost>".to_string()),
+        )?),
+        Err(_) => Arc::new(LogMailer::new(env::var("MAIL_DIR").ok().map(Into::into))),
+    };
 
     let pool = sqlx::postgres::PgPoolOptions::new()
         .max_connections(max_connections)
@@ -43,9 +67,20 @@ async fn main() -> anyhow::Result<(), anyhow::Error> {
 
     sqlx::migrate!("./migrations").run(&pool).await?;
 
-    let app = Router::new()
-        .route("/", get(health))
-        .route("/users", post(user::create_user))
+    let state = AppState {
+        pool,
+        keys: Arc::new(Keys::new(
+            jwt_secret.as_bytes(),
+            chrono::Duration::seconds(jwt_ttl),
+        )),
+        outbox: Arc::new(Outbox::new(mailer, env::var("APP_URL").ok())),
+        registration: Registration {
+            is_open: allow_registration,
+        },
+    };
+
+    // Everything here requires a valid bearer token
+    let protected = Router::new()
         .route(
             "/tables",
             get(table::get_tables)
@@ -55,6 +90,7 @@ async fn main() -> anyhow::Result<(), anyhow::Error> {
         .route(
             "/tables/:name",
             get(table::get_table)
+                .put(table::replace_table)
                 .delete(table::delete_table)
                 .patch(table::update_table),
         )
@@ -65,9 +101,54 @@ async fn main() -> anyhow::Result<(), anyhow::Error> {
                 .patch(row::update)
                 .delete(row::delete),
         )
+        .route("/rows/bulk", post(row::insert_many))
         .route("/rows/:id", get(row::select_one))
+        .route("/auth/me", get(handlers::auth::me))
+        .route(
+            "/auth/verify-email/request",
+            post(handlers::auth::request_email_verification),
+        )
+        .route("/users", get(user::get_users).post(user::create_user))
+        .route(
+            "/users/:id",
+            get(user::get_user)
+                .patch(user::update_user)
+                .delete(user::delete_user),
+        )
+        .route("/users/:id/password", put(user::change_password))
+        .route("/permissions", get(permission::get_permissions))
+        .route(
+            "/permissions/:role/:table",
+            put(permission::set_permissions).delete(permission::delete_permissions),
+        )
+        .route(
+            "/policies",
+            get(policy::get_policies).post(policy::create_policy),
+        )
+        .route("/policies/:id", delete(policy::delete_policy))
+        .route(
+            "/api-keys",
+            get(api_key::get_api_keys).post(api_key::create_api_key),
+        )
+        .route("/api-keys/:id", delete(api_key::revoke_api_key))
+        .route_layer(middleware::from_extractor_with_state::<AuthUser, _>(
+            state.clone(),
+        ));
+
+    let app = Router::new()
+        .route("/", get(health))
+        .route("/auth/register", post(handlers::auth::register))
+        .route("/auth/login", post(handlers::auth::login))
+        .route("/auth/verify-email", post(handlers::auth::verify_email))
+        .route(
+            "/auth/password-reset/request",
+            post(handlers::auth::request_password_reset),
+        )
+        .route("/auth/password-reset", post(handlers::auth::reset_password))
+        .merge(protected)
+        .layer(middleware::from_fn(request_id::propagate))
         .layer(CorsLayer::permissive())
-        .with_state(pool);
+        .with_state(state);
 
     let listener = TcpListener::bind(format!("{}:8000", ip_addr)).await?;
 
@@ -0,0 +1,23 @@
+use std::sync::Arc;
+
+use axum::extract::FromRef;
+use sqlx::PgPool;
+
+use crate::{auth::token::Keys, mail::Outbox};
+
+// Shared by every handler, extract `State<PgPool>`, `State<Arc<Keys>>`,
+// `State<Arc<Outbox>>` or `State<Registration>` directly
+#[derive(Clone, FromRef)]
+pub struct AppState {
+    pub pool: PgPool,
+    pub keys: Arc<Keys>,
+    pub outbox: Arc<Outbox>,
+    pub registration: Registration,
+}
+
+// Whether anyone can sign up through `/auth/register`, otherwise admins add users.
+// The first account can always register since it becomes the admin.
+#[derive(Debug, Clone, Copy)]
+pub struct Registration {
+    pub is_open: bool,
+}
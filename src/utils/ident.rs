@@ -0,0 +1,112 @@
+use std::fmt;
+
+use axum::http::StatusCode;
+
+use crate::error::AppError;
+
+// Postgres truncates identifiers longer than NAMEDATALEN - 1 bytes
+const MAX_IDENT_LEN: usize = 63;
+
+// A table or column name that is safe to push into SQL.
+// Always rendered double quoted, so names are matched exactly as given.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Ident(String);
+
+impl Ident {
+    // Accepts names matching `[A-Za-z_][A-Za-z0-9_]*`
+    pub fn parse(name: &str) -> Result<Self, AppError> {
+        let mut chars = name.chars();
+
+        let is_valid = name.len() <= MAX_IDENT_LEN
+            && chars
+                .next()
+                .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+        if !is_valid {
+            return Err(AppError::new(
+                StatusCode::BAD_REQUEST,
+                format!("Invalid identifier: `{}`", name),
+            ));
+        }
+
+        Ok(Self(name.to_string()))
+    }
+
+    // Parses a comma separated list of names, ignoring surrounding whitespace
+    pub fn parse_list(names: &str) -> Result<Vec<Self>, AppError> {
+        names
+            .split(',')
+            .map(|name| Self::parse(name.trim()))
+            .collect()
+    }
+
+    // For names read back from the catalog, which are known to exist
+    pub fn from_catalog(name: &str) -> Self {
+        Self(name.to_string())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for Ident {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "\"{}\"", self.0.replace('"', "\"\""))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_names() {
+        for name in [
+            "posts",
+            "BlogPosts",
+            "_draft",
+            "post_2",
+            &"a".repeat(MAX_IDENT_LEN),
+        ] {
+            assert_eq!(Ident::parse(name).unwrap().as_str(), name);
+        }
+    }
+
+    #[test]
+    fn invalid_names() {
+        for name in [
+            "",
+            "2posts",
+            "blog posts",
+            "posts;",
+            "\"posts\"",
+            "posts--",
+            "pöst",
+            &"a".repeat(MAX_IDENT_LEN + 1),
+        ] {
+            assert!(Ident::parse(name).is_err(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn lists() {
+        let names = Ident::parse_list("id, title ,views").unwrap();
+
+        assert_eq!(
+            names.iter().map(Ident::as_str).collect::<Vec<_>>(),
+            ["id", "title", "views"]
+        );
+        assert!(Ident::parse_list("id,,title").is_err());
+    }
+
+    #[test]
+    fn rendered_double_quoted() {
+        assert_eq!(
+            Ident::parse("BlogPosts").unwrap().to_string(),
+            "\"BlogPosts\""
+        );
+        assert_eq!(Ident::from_catalog("a\"b").to_string(), "\"a\"\"b\"");
+    }
+}
@@ -1,39 +1,201 @@
-use chrono::{DateTime, NaiveDateTime, Utc};
+use std::{fmt::Display, ops::Bound};
+
+use axum::http::StatusCode;
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, Utc};
 use serde_json::{json, Value};
 use sqlx::{
-    postgres::{PgColumn, PgRow},
-    Column, Row,
+    postgres::{
+        types::{PgInterval, PgMoney, PgRange, PgTimeTz},
+        PgColumn, PgRow, PgTypeInfo, PgTypeKind,
+    },
+    types::{ipnetwork::IpNetwork, mac_address::MacAddress, BigDecimal, Json},
+    Column, Postgres, QueryBuilder, Row, TypeInfo,
 };
+use tracing::warn;
+
+use crate::error::AppError;
+
+use self::schema::ColumnSchema;
+
+pub mod ident;
+pub mod schema;
+
+// Decodes `Option<$ty>` from the row and converts the inner value with `$map`
+macro_rules! decode {
+    ($row:expr, $index:expr, $ty:ty) => {
+        json!($row.try_get::<Option<$ty>, _>($index)?)
+    };
+    ($row:expr, $index:expr, $ty:ty, $map:expr) => {
+        json!($row.try_get::<Option<$ty>, _>($index)?.map($map))
+    };
+}
+
+// Same as `decode!` but for one dimensional arrays, where elements may be NULL
+macro_rules! decode_array {
+    ($row:expr, $index:expr, $ty:ty) => {
+        json!($row.try_get::<Option<Vec<Option<$ty>>>, _>($index)?)
+    };
+    ($row:expr, $index:expr, $ty:ty, $map:expr) => {
+        json!($row
+            .try_get::<Option<Vec<Option<$ty>>>, _>($index)?
+            .map(|items| items
+                .into_iter()
+                .map(|item| item.map($map))
+                .collect::<Vec<_>>()))
+    };
+}
+
+// Decodes a column into JSON based on its Postgres type.
+//
+// | Postgres type                          | JSON                                         |
+// |----------------------------------------|----------------------------------------------|
+// | bool                                   | boolean                                      |
+// | int2, int4, int8, oid                  | number                                       |
+// | float4, float8                         | number, or null for NaN and infinity         |
+// | numeric, money                         | string, to keep the exact precision          |
+// | text, varchar, char, name, citext      | string                                       |
+// | enums                                  | string with the enum label                   |
+// | uuid                                   | string                                       |
+// | json, jsonb                            | the JSON value as stored                     |
+// | date                                   | string, "2024-01-31"                         |
+// | time, timetz                           | string, "13:45:00" or "13:45:00+08:00"       |
+// | timestamp                              | string, "2024-01-31T13:45:00"                |
+// | timestamptz                            | string in UTC, "2024-01-31T13:45:00Z"        |
+// | interval                               | object, { months, days, microseconds }       |
+// | bytea                                  | string in hex format, "\\xdeadbeef"           |
+// | inet, cidr, macaddr                    | string                                       |
+// | int4range, int8range, numrange,        | string in range format, "[1,10)"             |
+// | daterange, tsrange, tstzrange          |                                              |
+// | one dimensional arrays of the above    | array                                        |
+//
+// Anything else is returned as null and logged.
+pub fn get_value_from_row(row: &PgRow, column: &PgColumn) -> Value {
+    decode_value(row, column.ordinal(), column.type_info()).unwrap_or_else(|error| {
+        warn!(
+            "Failed to decode column `{}` of type {}: {}",
+            column.name(),
+            column.type_info().name(),
+            error
+        );
+
+        Value::Null
+    })
+}
+
+fn decode_value(row: &PgRow, index: usize, type_info: &PgTypeInfo) -> Result<Value, sqlx::Error> {
+    // Enums are sent as their label, SQLx only needs to be told to skip the type check
+    match type_info.kind() {
+        PgTypeKind::Enum(_) => {
+            return Ok(json!(row.try_get_unchecked::<Option<String>, _>(index)?));
+        }
+        PgTypeKind::Array(element) if matches!(element.kind(), PgTypeKind::Enum(_)) => {
+            return Ok(json!(
+                row.try_get_unchecked::<Option<Vec<Option<String>>>, _>(index)?
+            ));
+        }
+        _ => {}
+    }
+
+    let value = match type_info.name() {
+        "BOOL" => decode!(row, index, bool),
+        "INT2" => decode!(row, index, i16),
+        "INT4" => decode!(row, index, i32),
+        "INT8" => decode!(row, index, i64),
+        "OID" => decode!(row, index, sqlx::postgres::types::Oid, |oid| oid.0),
+        "FLOAT4" => decode!(row, index, f32),
+        "FLOAT8" => decode!(row, index, f64),
+        "NUMERIC" => decode!(row, index, BigDecimal, |n| n.to_string()),
+        "MONEY" => decode!(row, index, PgMoney, money_to_string),
+        "TEXT" | "VARCHAR" | "CHAR" | "NAME" | "citext" => decode!(row, index, String),
+        "\"CHAR\"" => decode!(row, index, i8, |c| (c as u8 as char).to_string()),
+        "UUID" => decode!(row, index, uuid::Uuid),
+        "JSON" | "JSONB" => decode!(row, index, Json<Value>, |json| json.0),
+        "DATE" => decode!(row, index, NaiveDate),
+        "TIME" => decode!(row, index, NaiveTime),
+        "TIMETZ" => decode!(row, index, PgTimeTz<NaiveTime, FixedOffset>, |t| format!(
+            "{}{}",
+            t.time, t.offset
+        )),
+        "TIMESTAMP" => decode!(row, index, NaiveDateTime),
+        "TIMESTAMPTZ" => decode!(row, index, DateTime<Utc>),
+        "INTERVAL" => decode!(row, index, PgInterval, interval_to_json),
+        "BYTEA" => decode!(row, index, Vec<u8>, |bytes| bytes_to_hex(&bytes)),
+        "INET" | "CIDR" => decode!(row, index, IpNetwork, |ip| ip.to_string()),
+        "MACADDR" => decode!(row, index, MacAddress, |mac| mac.to_string()),
+        "INT4RANGE" => decode!(row, index, PgRange<i32>, range_to_string),
+        "INT8RANGE" => decode!(row, index, PgRange<i64>, range_to_string),
+        "NUMRANGE" => decode!(row, index, PgRange<BigDecimal>, range_to_string),
+        "DATERANGE" => decode!(row, index, PgRange<NaiveDate>, range_to_string),
+        "TSRANGE" => decode!(row, index, PgRange<NaiveDateTime>, range_to_string),
+        "TSTZRANGE" => decode!(row, index, PgRange<DateTime<Utc>>, range_to_string),
+
+        "BOOL[]" => decode_array!(row, index, bool),
+        "INT2[]" => decode_array!(row, index, i16),
+        "INT4[]" => decode_array!(row, index, i32),
+        "INT8[]" => decode_array!(row, index, i64),
+        "FLOAT4[]" => decode_array!(row, index, f32),
+        "FLOAT8[]" => decode_array!(row, index, f64),
+        "NUMERIC[]" => decode_array!(row, index, BigDecimal, |n| n.to_string()),
+        "TEXT[]" | "VARCHAR[]" | "CHAR[]" | "NAME[]" => decode_array!(row, index, String),
+        "UUID[]" => decode_array!(row, index, uuid::Uuid),
+        "JSON[]" | "JSONB[]" => decode_array!(row, index, Json<Value>, |json| json.0),
+        "DATE[]" => decode_array!(row, index, NaiveDate),
+        "TIME[]" => decode_array!(row, index, NaiveTime),
+        "TIMESTAMP[]" => decode_array!(row, index, NaiveDateTime),
+        "TIMESTAMPTZ[]" => decode_array!(row, index, DateTime<Utc>),
+        "INET[]" | "CIDR[]" => decode_array!(row, index, IpNetwork, |ip| ip.to_string()),
+
+        name => {
+            warn!("Unsupported column type: {}", name);
+
+            Value::Null
+        }
+    };
+
+    Ok(value)
+}
+
+// NOTE: Assumes the default `lc_monetary` of two fractional digits
+fn money_to_string(money: PgMoney) -> String {
+    money.to_bigdecimal(2).to_string()
+}
+
+fn interval_to_json(interval: PgInterval) -> Value {
+    json!({
+        "months": interval.months,
+        "days": interval.days,
+        "microseconds": interval.microseconds,
+    })
+}
+
+fn bytes_to_hex(bytes: &[u8]) -> String {
+    let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+
+    format!("\\x{}", hex)
+}
+
+fn range_to_string<T: Display>(range: PgRange<T>) -> String {
+    let start = match range.start {
+        Bound::Included(ref value) => format!("[{}", value),
+        Bound::Excluded(ref value) => format!("({}", value),
+        Bound::Unbounded => "(".to_string(),
+    };
 
-// NOTE: Postgres has a LOT of data types, only support the commonly used ones for now
-// A better way to handle this is to pattern match SQLx's PgColumn type_info
-pub fn get_value_from_row(row: &PgRow, field: &str) -> Value {
-    let value: Value = if let Ok(val) = row.try_get::<Option<uuid::Uuid>, _>(field) {
-        json!(val)
-    } else if let Ok(val) = row.try_get::<Option<String>, _>(field) {
-        // Text and varchar
-        json!(val)
-    } else if let Ok(val) = row.try_get::<Option<i16>, _>(field) {
-        json!(val)
-    } else if let Ok(val) = row.try_get::<Option<i32>, _>(field) {
-        json!(val)
-    } else if let Ok(val) = row.try_get::<Option<f32>, _>(field) {
-        json!(val)
-    } else if let Ok(val) = row.try_get::<Option<f64>, _>(field) {
-        json!(val)
-    } else if let Ok(val) = row.try_get::<Option<NaiveDateTime>, _>(field) {
-        // Timestamp without timezone
-        json!(val)
-    } else if let Ok(val) = row.try_get::<Option<DateTime<Utc>>, _>(field) {
-        // Timestamp with timezone
-        json!(val)
-    } else if let Ok(val) = row.try_get::<Option<bool>, _>(field) {
-        json!(val)
-    } else {
-        json!(null)
+    let end = match range.end {
+        Bound::Included(ref value) => format!("{}]", value),
+        Bound::Excluded(ref value) => format!("{})", value),
+        Bound::Unbounded => ")".to_string(),
     };
 
-    value
+    format!("{},{}", start, end)
+}
+
+pub fn row_to_json(row: &PgRow) -> Value {
+    let mut map = serde_json::Map::new();
+
+    insert_col_to_map(row, row.columns(), &mut map);
+
+    Value::Object(map)
 }
 
 pub fn insert_col_to_map(
@@ -42,20 +204,130 @@ pub fn insert_col_to_map(
     map: &mut serde_json::Map<String, Value>,
 ) {
     for col in columns {
-        let name = col.name();
-        let type_info = col.type_info();
+        let value = get_value_from_row(row, col);
 
-        let value = get_value_from_row(row, name);
+        map.insert(col.name().to_string(), value);
+    }
+}
+
+// Expressions that may be pushed as-is when a value is flagged with `is_db_expression`
+const DB_EXPRESSIONS: &[&str] = &[
+    "DEFAULT",
+    "gen_random_uuid()",
+    "now()",
+    "current_date",
+    "current_time",
+    "current_timestamp",
+];
+
+// Pushes a user-supplied value as a bind parameter, cast to the column's Postgres type
+// without modifiers, since an explicit cast to `varchar(n)` silently truncates.
+// Values are bound as text unless a more specific encoding exists, and Postgres does
+// the conversion through the cast so every column type is supported.
+pub fn push_bind_value(
+    q_builder: &mut QueryBuilder<'_, Postgres>,
+    value: &Value,
+    column: &ColumnSchema,
+) {
+    match (value, column.udt_name.as_str()) {
+        (Value::Null, _) => {
+            q_builder.push_bind(None::<String>);
+        }
+        (_, "json" | "jsonb") => {
+            q_builder.push_bind(Json(value.clone()));
+        }
+        (Value::Bool(b), _) => {
+            q_builder.push_bind(*b);
+        }
+        (Value::String(s), _) => {
+            q_builder.push_bind(s.clone());
+        }
+        (Value::Array(items), udt_name) if udt_name.starts_with('_') => {
+            let items: Vec<Option<String>> = items
+                .iter()
+                .map(|item| match item {
+                    Value::Null => None,
+                    Value::String(s) => Some(s.clone()),
+                    _ => Some(item.to_string()),
+                })
+                .collect();
 
-        map.insert(name.to_string(), value);
+            q_builder.push_bind(items);
+        }
+        _ => {
+            // Numbers are bound as text so `numeric` columns keep their exact precision
+            q_builder.push_bind(value.to_string());
+        }
     }
+
+    q_builder.push(format_args!("::{}", column.base_type));
+}
+
+// Pushes a value flagged as a database expression, e.g. `now()`.
+// Only a fixed set of expressions is accepted since they are not bound.
+pub fn push_db_expression(
+    q_builder: &mut QueryBuilder<'_, Postgres>,
+    value: &Value,
+) -> Result<(), AppError> {
+    let expression = value.as_str().and_then(db_expression).ok_or_else(|| {
+        AppError::new(
+            StatusCode::BAD_REQUEST,
+            format!("Unsupported database expression: {}", value),
+        )
+    })?;
+
+    q_builder.push(expression);
+
+    Ok(())
+}
+
+// The entry of `DB_EXPRESSIONS` matching `s`, ignoring case
+pub fn db_expression(s: &str) -> Option<&'static str> {
+    DB_EXPRESSIONS
+        .iter()
+        .find(|expr| expr.eq_ignore_ascii_case(s.trim()))
+        .copied()
 }
 
-// pub fn to_sql_string(value: &Value) {
-//     match value {
-//         Value::String(s) => {
-//             format_args!("'{}'", s)
-//         }
-//         _ => value,
-//     }
-// }
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn column(data_type: &str, base_type: &str, udt_name: &str) -> ColumnSchema {
+        ColumnSchema {
+            name: "title".to_string(),
+            data_type: data_type.to_string(),
+            base_type: base_type.to_string(),
+            udt_name: udt_name.to_string(),
+            is_nullable: true,
+            default: None,
+            is_primary_key: false,
+            primary_key_position: None,
+        }
+    }
+
+    fn bind(value: Value, column: &ColumnSchema) -> String {
+        let mut q_builder = QueryBuilder::new("VALUES (");
+
+        push_bind_value(&mut q_builder, &value, column);
+        q_builder.push(")");
+
+        q_builder.sql().to_string()
+    }
+
+    #[test]
+    fn casts_leave_out_modifiers() {
+        let title = column("character varying(20)", "character varying", "varchar");
+        let tags = column("character(3)[]", "character[]", "_bpchar");
+        let price = column("numeric(10,2)", "numeric", "numeric");
+
+        assert_eq!(
+            bind(json!("Hello"), &title),
+            "VALUES ($1::character varying)"
+        );
+        assert_eq!(bind(json!(["abc"]), &tags), "VALUES ($1::character[])");
+        assert_eq!(bind(json!(1.5), &price), "VALUES ($1::numeric)");
+    }
+}
@@ -0,0 +1,210 @@
+use axum::http::StatusCode;
+use serde::Serialize;
+use sqlx::{prelude::FromRow, PgExecutor};
+
+use crate::error::AppError;
+
+use super::ident::Ident;
+
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct ColumnSchema {
+    pub name: String,
+    // Output of `format_type()`, e.g. "character varying(255)" or "integer[]"
+    pub data_type: String,
+    // The type without modifiers, e.g. "character varying", which values are cast to
+    // so they're checked against the length when stored rather than truncated
+    pub base_type: String,
+    // Internal type name, e.g. "varchar" or "_int4" for arrays
+    pub udt_name: String,
+    pub is_nullable: bool,
+    // Default expression as stored, e.g. "'draft'::text" or "now()"
+    pub default: Option<String>,
+    pub is_primary_key: bool,
+    // 1-based position within a composite primary key
+    pub primary_key_position: Option<i32>,
+}
+
+impl ColumnSchema {
+    pub fn ident(&self) -> Ident {
+        Ident::from_catalog(&self.name)
+    }
+}
+
+// A primary key, unique, check, foreign key or exclusion constraint on a table
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct ConstraintSchema {
+    pub name: String,
+    // `contype` from `pg_constraint`: "p", "u", "c", "f" or "x"
+    pub kind: String,
+    // In constraint order, empty for checks on expressions only
+    pub columns: Vec<String>,
+    // Output of `pg_get_constraintdef()`, e.g. "CHECK ((price > 0))"
+    pub definition: String,
+}
+
+impl ConstraintSchema {
+    pub fn is_primary_key(&self) -> bool {
+        self.kind == "p"
+    }
+
+    // Unique constraint on exactly this column
+    pub fn is_unique_on(&self, column: &str) -> bool {
+        self.kind == "u" && self.columns.len() == 1 && self.columns[0] == column
+    }
+}
+
+// Column layout of a table as it currently exists in the database
+#[derive(Debug, Clone)]
+pub struct TableSchema {
+    pub name: Ident,
+    pub columns: Vec<ColumnSchema>,
+}
+
+impl TableSchema {
+    pub async fn fetch<'e, E>(executor: E, table: &Ident) -> Result<Self, AppError>
+    where
+        E: PgExecutor<'e>,
+    {
+        let columns = sqlx::query_as::<_, ColumnSchema>(
+            r#"
+            SELECT
+                a.attname AS name,
+                format_type(a.atttypid, a.atttypmod) AS data_type,
+                format_type(a.atttypid, NULL) AS base_type,
+                t.typname AS udt_name,
+                NOT a.attnotnull AS is_nullable,
+                pg_get_expr(d.adbin, d.adrelid) AS "default",
+                pk.position IS NOT NULL AS is_primary_key,
+                pk.position AS primary_key_position
+            FROM pg_class c
+            JOIN pg_namespace n ON n.oid = c.relnamespace
+            JOIN pg_attribute a ON a.attrelid = c.oid
+            JOIN pg_type t ON t.oid = a.atttypid
+            LEFT JOIN pg_attrdef d ON d.adrelid = c.oid AND d.adnum = a.attnum
+            LEFT JOIN LATERAL (
+                SELECT array_position(i.indkey::int2[], a.attnum) AS position
+                FROM pg_index i
+                WHERE i.indrelid = c.oid AND i.indisprimary
+            ) pk ON true
+            WHERE
+                n.nspname = 'public'
+                AND c.relname = ($1)
+                AND c.relkind IN ('r', 'p', 'v', 'm', 'f')
+                AND a.attnum > 0
+                AND NOT a.attisdropped
+            ORDER BY a.attnum;
+            "#,
+        )
+        .bind(table.as_str())
+        .fetch_all(executor)
+        .await?;
+
+        if columns.is_empty() {
+            return Err(AppError::new(
+                StatusCode::NOT_FOUND,
+                format!("Table `{}` does not exist.", table.as_str()),
+            ));
+        }
+
+        Ok(Self {
+            name: table.clone(),
+            columns,
+        })
+    }
+
+    // Column sets of the primary key and every unique index that can be used as an
+    // ON CONFLICT target, i.e. without expressions or a WHERE predicate
+    pub async fn unique_keys<'e, E>(&self, executor: E) -> Result<Vec<Vec<String>>, AppError>
+    where
+        E: PgExecutor<'e>,
+    {
+        let keys: Vec<Vec<String>> = sqlx::query_scalar(
+            r#"
+            SELECT
+                ARRAY(
+                    SELECT a.attname::text
+                    FROM unnest(i.indkey::int2[]) WITH ORDINALITY AS k(attnum, position)
+                    JOIN pg_attribute a ON a.attrelid = i.indrelid AND a.attnum = k.attnum
+                    ORDER BY k.position
+                )
+            FROM pg_index i
+            JOIN pg_class c ON c.oid = i.indrelid
+            JOIN pg_namespace n ON n.oid = c.relnamespace
+            WHERE
+                n.nspname = 'public'
+                AND c.relname = ($1)
+                AND i.indisunique
+                AND i.indexprs IS NULL
+                AND i.indpred IS NULL
+            ORDER BY i.indisprimary DESC, i.indexrelid;
+            "#,
+        )
+        .bind(self.name.as_str())
+        .fetch_all(executor)
+        .await?;
+
+        Ok(keys)
+    }
+
+    pub async fn constraints<'e, E>(&self, executor: E) -> Result<Vec<ConstraintSchema>, AppError>
+    where
+        E: PgExecutor<'e>,
+    {
+        let constraints = sqlx::query_as::<_, ConstraintSchema>(
+            r#"
+            SELECT
+                con.conname::text AS name,
+                con.contype::text AS kind,
+                ARRAY(
+                    SELECT a.attname::text
+                    FROM unnest(con.conkey) WITH ORDINALITY AS k(attnum, position)
+                    JOIN pg_attribute a ON a.attrelid = con.conrelid AND a.attnum = k.attnum
+                    ORDER BY k.position
+                ) AS columns,
+                pg_get_constraintdef(con.oid) AS definition
+            FROM pg_constraint con
+            JOIN pg_class c ON c.oid = con.conrelid
+            JOIN pg_namespace n ON n.oid = c.relnamespace
+            WHERE
+                n.nspname = 'public'
+                AND c.relname = ($1)
+                AND con.contype IN ('p', 'u', 'c', 'f', 'x')
+            ORDER BY con.contype, con.conname;
+            "#,
+        )
+        .bind(self.name.as_str())
+        .fetch_all(executor)
+        .await?;
+
+        Ok(constraints)
+    }
+
+    // Primary key columns in the order they were declared in the constraint
+    pub fn primary_key(&self) -> Vec<&ColumnSchema> {
+        let mut columns: Vec<&ColumnSchema> = self
+            .columns
+            .iter()
+            .filter(|col| col.is_primary_key)
+            .collect();
+
+        columns.sort_by_key(|col| col.primary_key_position);
+
+        columns
+    }
+
+    pub fn column(&self, name: &str) -> Result<&ColumnSchema, AppError> {
+        self.columns
+            .iter()
+            .find(|col| col.name == name)
+            .ok_or_else(|| {
+                AppError::new(
+                    StatusCode::BAD_REQUEST,
+                    format!(
+                        "Column `{}` does not exist on table `{}`.",
+                        name,
+                        self.name.as_str()
+                    ),
+                )
+            })
+    }
+}